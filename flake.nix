@@ -135,6 +135,7 @@
             # numpy
             pyarrow
             fastparquet
+            boto3
           ]);
       in {
         treefmt.config = {
@@ -63,6 +63,18 @@ resource "aws_dynamodb_table" "mockRequests" {
     name = "creationDate"
     type = "N"
   }
+
+  attribute {
+    name = "jobStatus"
+    type = "S"
+  }
+
+  global_secondary_index {
+    name            = "StatusIndex"
+    hash_key        = "jobStatus"
+    range_key       = "creationDate"
+    projection_type = "ALL"
+  }
 }
 
 resource "aws_dynamodb_table" "jobs" {
@@ -80,10 +92,27 @@ resource "aws_dynamodb_table" "jobs" {
     type = "S"
   }
 
+  attribute {
+    name = "workerID"
+    type = "S"
+  }
+
+  attribute {
+    name = "jobStatus"
+    type = "S"
+  }
+
   global_secondary_index {
     name            = "RequestIDIndex"
     hash_key        = "requestID"
-    projection_type = "KEYS_ONLY"
+    projection_type = "ALL"
+  }
+
+  global_secondary_index {
+    name            = "WorkerIDIndex"
+    hash_key        = "workerID"
+    range_key       = "jobStatus"
+    projection_type = "ALL"
   }
 }
 
@@ -152,8 +152,19 @@ resource "aws_ecs_service" "visilake" {
 }
 
 resource "aws_sqs_queue" "request_queue" {
-  name                      = "requestQueue"
-  message_retention_seconds = 60 # for localstack only
+  name                       = "requestQueue"
+  visibility_timeout_seconds = 300  # matches visiproc's [intake] visibility_timeout
+  message_retention_seconds  = 3600 # outlasts every redelivery before the message is dead-lettered
+  redrive_policy = jsonencode({
+    deadLetterTargetArn = aws_sqs_queue.request_dead_letter_queue.arn
+    maxReceiveCount     = 5
+  })
+}
+
+# Requests visiproc could not read or find, after maxReceiveCount deliveries
+resource "aws_sqs_queue" "request_dead_letter_queue" {
+  name                      = "requestQueueDLQ"
+  message_retention_seconds = 1209600
 }
 
 resource "aws_sns_topic" "request_updates" {
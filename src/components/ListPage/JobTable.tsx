@@ -274,6 +274,8 @@ export default function JobTable() {
           PROCESSING: 'Data is currently being processed',
           COMPLETE: 'Processing job has been completed',
           FAILED: 'An error has occurred',
+          CANCEL_REQUESTED: 'Processing job is being cancelled',
+          CANCELLED: 'Processing job was cancelled',
         };
 
         return tooltipMessages[params.value as string] ?? 'INVALID';
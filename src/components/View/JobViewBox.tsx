@@ -1,5 +1,6 @@
 import { Job } from '~/models/domain/job';
 import { api } from '~/utils/api';
+import { jobLogUrls } from '~/utils/jobArtifacts.mjs';
 
 interface JobViewBoxProps {
   job: Job;
@@ -8,12 +9,13 @@ interface JobViewBoxProps {
 export default function JobViewBox({ job }: JobViewBoxProps) {
   const { data: s3URL, isLoading } = api.s3.getS3Url.useQuery();
 
-  const getS3Url = () => {
+  const getS3Urls = () => {
     if (job.jobStatus == 'COMPLETE') {
-      return `${s3URL}metadata/${job.jobId}/${job.jobId}-eda.html`;
+      return [`${s3URL}metadata/${job.jobId}/${job.jobId}-eda.html`];
     } else if (job.jobStatus == 'FAILED') {
-      return `${s3URL}metadata/${job.jobId}/${job.jobId}-error.log`;
+      return jobLogUrls(s3URL ?? '', job);
     }
+    return [];
   };
 
   return (
@@ -84,12 +86,15 @@ export default function JobViewBox({ job }: JobViewBoxProps) {
           isLoading ? (
             <h1>Loading data...</h1>
           ) : (
-            <iframe
-              src={getS3Url()}
-              title='HTML Content'
-              className='h-full min-h-screen w-full'
-              style={{ pointerEvents: 'auto', outline: 'none' }}
-            ></iframe>
+            getS3Urls().map((url) => (
+              <iframe
+                key={url}
+                src={url}
+                title='HTML Content'
+                className='h-full min-h-screen w-full'
+                style={{ pointerEvents: 'auto', outline: 'none' }}
+              ></iframe>
+            ))
           )
         ) : (
           <></>
@@ -3,7 +3,9 @@ export type JobStatus =
   | 'QUEUED'
   | 'PROCESSING'
   | 'COMPLETE'
-  | 'FAILED';
+  | 'FAILED'
+  | 'CANCEL_REQUESTED'
+  | 'CANCELLED';
 
 export const statusOrder: { [key in JobStatus]: number } = {
   PENDING: 1,
@@ -11,4 +13,6 @@ export const statusOrder: { [key in JobStatus]: number } = {
   PROCESSING: 3,
   COMPLETE: 4,
   FAILED: 5,
+  CANCEL_REQUESTED: 6,
+  CANCELLED: 7,
 };
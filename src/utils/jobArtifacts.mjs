@@ -0,0 +1,36 @@
+/**
+ * The slug visiproc appends to a request id to name the job running each analysis type, as in
+ * `JobType::slug`.
+ * @type {Record<string, string>}
+ */
+const analysisTypeSlugs = {
+  'Exploratory Data Analysis': 'eda',
+  Correlation: 'correlation',
+  'Simulated Job': 'simulated',
+  'Simulated Error': 'simulated-error',
+};
+
+/**
+ * The id of the job running `analysisType` for the request `requestId`.
+ * @param {string} requestId
+ * @param {string} analysisType
+ * @returns {string | undefined}
+ */
+export function analysisJobId(requestId, analysisType) {
+  const slug = analysisTypeSlugs[analysisType];
+  return slug ? `${requestId}-${slug}` : undefined;
+}
+
+/**
+ * Links to the logs of every job of the request, which visiproc uploads as
+ * `metadata/<request id>/<job id>.log`.
+ * @param {string} s3URL
+ * @param {{ jobId: string, analysisTypes: string[] }} job
+ * @returns {string[]}
+ */
+export function jobLogUrls(s3URL, job) {
+  return job.analysisTypes
+    .map((analysisType) => analysisJobId(job.jobId, analysisType))
+    .filter((jobId) => jobId !== undefined)
+    .map((jobId) => `${s3URL}metadata/${job.jobId}/${jobId}.log`);
+}
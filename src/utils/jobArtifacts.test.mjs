@@ -0,0 +1,21 @@
+import assert from 'node:assert/strict';
+import { test } from 'node:test';
+import { analysisJobId, jobLogUrls } from './jobArtifacts.mjs';
+
+test('jobs are named after their request and analysis type', () => {
+  assert.equal(analysisJobId('abc', 'Exploratory Data Analysis'), 'abc-eda');
+  assert.equal(analysisJobId('abc', 'Simulated Error'), 'abc-simulated-error');
+  assert.equal(analysisJobId('abc', 'Unknown'), undefined);
+});
+
+test('failed requests link the log of each of their jobs', () => {
+  const job = {
+    jobId: 'abc',
+    analysisTypes: ['Exploratory Data Analysis', 'Correlation', 'Unknown'],
+  };
+
+  assert.deepEqual(jobLogUrls('http://localhost:4566/', job), [
+    'http://localhost:4566/metadata/abc/abc-eda.log',
+    'http://localhost:4566/metadata/abc/abc-correlation.log',
+  ]);
+});
@@ -1,154 +1,527 @@
-use crate::models::{job::Job, job_type::JobType};
-use eyre::{eyre, Result};
-use log::debug;
-use std::sync::{Arc, Mutex};
-use std::{future::Future, pin::Pin, process::Command};
-
-struct CorrelationJob;
-struct EdaJob;
-struct SimulatedJob;
-struct SimulatedError;
-
-pub trait AnalysisJob {
-    fn run(&self, job: Arc<Mutex<Job>>) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
-    fn handle_result(&self, job_id: &str, temp_path: &str) -> Result<()>;
-    fn type_name(&self) -> &'static str;
-    fn script_path(&self) -> &'static str;
-}
+use super::{errors::JobError, manifest::Manifest};
+use crate::{
+    aws::s3::{mime_type, parse_s3_uri},
+    config::Config,
+    models::{artifact::Artifact, job::Job, job_type::JobType},
+    store::BlobStore,
+};
+use eyre::Result;
+use log::{debug, info, warn};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::{
+    future::Future,
+    os::unix::process::CommandExt,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    process::{Command, Stdio},
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 
-impl AnalysisJob for EdaJob {
-    fn run(&self, job: Arc<Mutex<Job>>) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
-        run_job(self, job)
-    }
+/// How an analysis script is run: `<interpreter> <path> <s3 path> <request id>`, with
+/// `VISIPROC_OUTPUT_DIR` set to the directory it should write its artifacts to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    pub interpreter: String,
+    pub path: PathBuf,
+    pub env: Vec<(String, String)>, // set on top of visiproc's own environment
+}
 
-    fn handle_result(&self, job_id: &str, temp_path: &str) -> Result<()> {
-        let output = Command::new("awslocal")
-            .arg("s3")
-            .arg("cp")
-            .arg(temp_path)
-            .arg(format!("s3://metadata/{}/{}-eda.html", job_id, job_id))
-            .output()?;
+struct CorrelationJob {
+    script: Script,
+}
+struct EdaJob {
+    script: Script,
+}
+struct SimulatedJob {
+    script: Script,
+}
+struct SimulatedError {
+    script: Script,
+}
 
-        if !output.status.success() {
-            let error_message = String::from_utf8_lossy(&output.stderr);
-            return Err(eyre!("S3 eda report upload failed: {}", error_message));
-        }
+pub type ResultFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<Artifact>>> + Send + 'a>>;
 
-        let output_data = Command::new("awslocal")
-            .arg("s3")
-            .arg("cp")
-            .arg(temp_path.replace("-eda.html", "-data.parquet"))
-            .arg(format!("s3://metadata/{}/{}-data.parquet", job_id, job_id))
-            .output()?;
+pub trait AnalysisJob: Send + Sync {
+    /// Runs the job once, collecting the script's output in `log`.
+    fn run(
+        &self,
+        job: Arc<Mutex<Job>>,
+        blobs: Arc<dyn BlobStore>,
+        log: ScriptLog,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>;
+    /// Uploads the artifacts listed in the manifest of a successful run, returning where they went.
+    fn handle_result<'a>(
+        &'a self,
+        blobs: &'a dyn BlobStore,
+        job: &'a Job,
+        manifest: &'a Manifest,
+    ) -> ResultFuture<'a> {
+        Box::pin(upload_artifacts(blobs, job, manifest))
+    }
+    fn job_type(&self) -> JobType;
+    fn type_name(&self) -> &'static str;
+    fn script(&self) -> &Script;
+}
 
-        if !output_data.status.success() {
-            let error_message = String::from_utf8_lossy(&output_data.stderr);
-            return Err(eyre!("S3 data upload failed: {}", error_message));
-        }
+impl AnalysisJob for EdaJob {
+    fn run(
+        &self,
+        job: Arc<Mutex<Job>>,
+        blobs: Arc<dyn BlobStore>,
+        log: ScriptLog,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        run_job(self, job, blobs, log)
+    }
 
-        debug!("Job result file uploaded for {}", job_id);
-        Ok(())
+    fn job_type(&self) -> JobType {
+        JobType::Eda
     }
 
     fn type_name(&self) -> &'static str {
         "EdaJob"
     }
 
-    fn script_path(&self) -> &'static str {
-        "python_jobs/eda_analysis.py"
+    fn script(&self) -> &Script {
+        &self.script
     }
 }
 
 impl AnalysisJob for CorrelationJob {
-    fn run(&self, job: Arc<Mutex<Job>>) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
-        run_job(self, job)
+    fn run(
+        &self,
+        job: Arc<Mutex<Job>>,
+        blobs: Arc<dyn BlobStore>,
+        log: ScriptLog,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        run_job(self, job, blobs, log)
     }
 
-    fn handle_result(&self, job_id: &str, temp_path: &str) -> Result<()> {
-        debug!("Correlation Job: {} - {}", job_id, temp_path);
-        Ok(())
+    fn job_type(&self) -> JobType {
+        JobType::Corr
     }
 
     fn type_name(&self) -> &'static str {
         "Correlation Job"
     }
 
-    fn script_path(&self) -> &'static str {
-        "python_jobs/correlation_analysis.py"
+    fn script(&self) -> &Script {
+        &self.script
     }
 }
 
 impl AnalysisJob for SimulatedJob {
-    fn run(&self, job: Arc<Mutex<Job>>) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
-        run_job(self, job)
+    fn run(
+        &self,
+        job: Arc<Mutex<Job>>,
+        blobs: Arc<dyn BlobStore>,
+        log: ScriptLog,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        run_job(self, job, blobs, log)
     }
 
-    fn handle_result(&self, job_id: &str, temp_path: &str) -> Result<()> {
-        debug!("Simulated job: {} - {}", job_id, temp_path);
-        Ok(())
+    fn handle_result<'a>(
+        &'a self,
+        _blobs: &'a dyn BlobStore,
+        job: &'a Job,
+        manifest: &'a Manifest,
+    ) -> ResultFuture<'a> {
+        // simulated results stay local, so the happy path runs without AWS
+        Box::pin(async move {
+            debug!("Simulated job: {} - {:?}", job.job_id, manifest);
+            Ok(Vec::new())
+        })
+    }
+
+    fn job_type(&self) -> JobType {
+        JobType::SimulatedJob
     }
 
     fn type_name(&self) -> &'static str {
         "Simulated Job"
     }
-    fn script_path(&self) -> &'static str {
-        "python_jobs/simulated_analysis.py"
+    fn script(&self) -> &Script {
+        &self.script
     }
 }
 
 impl AnalysisJob for SimulatedError {
-    fn run(&self, job: Arc<Mutex<Job>>) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
-        run_job(self, job)
+    fn run(
+        &self,
+        job: Arc<Mutex<Job>>,
+        blobs: Arc<dyn BlobStore>,
+        log: ScriptLog,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        run_job(self, job, blobs, log)
     }
 
-    fn handle_result(&self, job_id: &str, temp_path: &str) -> Result<()> {
-        debug!("Simulated Error: {} - {}", job_id, temp_path);
-        Ok(())
+    fn handle_result<'a>(
+        &'a self,
+        _blobs: &'a dyn BlobStore,
+        job: &'a Job,
+        manifest: &'a Manifest,
+    ) -> ResultFuture<'a> {
+        Box::pin(async move {
+            debug!("Simulated Error: {} - {:?}", job.job_id, manifest);
+            Ok(Vec::new())
+        })
+    }
+
+    fn job_type(&self) -> JobType {
+        JobType::SimulatedError
     }
 
     fn type_name(&self) -> &'static str {
         "Simulated Error"
     }
-    fn script_path(&self) -> &'static str {
-        "python_jobs/simulated_error.py"
+    fn script(&self) -> &Script {
+        &self.script
     }
 }
 
-pub fn create_job_instance(job_type: JobType) -> Box<dyn AnalysisJob> {
+pub fn create_job_instance(job_type: JobType, config: &Config) -> Arc<dyn AnalysisJob> {
+    let script = config.script(job_type);
     match job_type {
-        JobType::Corr => Box::new(CorrelationJob {}),
-        JobType::Eda => Box::new(EdaJob {}),
-        JobType::SimulatedJob => Box::new(SimulatedJob {}),
-        JobType::SimulatedError => Box::new(SimulatedError {}),
+        JobType::Corr => Arc::new(CorrelationJob { script }),
+        JobType::Eda => Arc::new(EdaJob { script }),
+        JobType::SimulatedJob => Arc::new(SimulatedJob { script }),
+        JobType::SimulatedError => Arc::new(SimulatedError { script }),
         JobType::None => panic!("Invalid job type for execution"),
     }
 }
 
-fn run_job<T: AnalysisJob + ?Sized + std::marker::Sync>(
+fn run_job<T: AnalysisJob + ?Sized>(
     analysis_job: &T,
     job: Arc<Mutex<Job>>,
+    blobs: Arc<dyn BlobStore>,
+    log: ScriptLog,
 ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
     Box::pin(async move {
-        let locked_job = job.lock().unwrap(); // Lock to access job data
-        let job_id = &locked_job.request_id.clone();
-
-        let output = Command::new("python")
-            .arg(analysis_job.script_path())
-            .arg(&locked_job.s3_path)
-            .arg(&locked_job.request_id)
-            .output()?;
-        drop(locked_job);
-
-        if !output.status.success() {
-            let error_message = String::from_utf8_lossy(&output.stderr);
-            return Err(eyre!("Job analysis run failed: {}", error_message));
+        let snapshot = job.lock().unwrap().clone(); // lock dropped here, before the script runs
+        let script = analysis_job.script();
+        let mut command = Command::new(&script.interpreter);
+        command
+            .arg(&script.path)
+            .arg(&snapshot.s3_path)
+            .arg(&snapshot.request_id)
+            .env("VISIPROC_OUTPUT_DIR", output_dir(&snapshot))
+            .envs(script.env.iter().cloned());
+
+        // the script leads its own process group so it can be killed along with anything it spawns
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        let mut child = tokio::process::Command::from(command)
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut process_group = ProcessGroup(child.id());
+        let _fallback = LogFallback {
+            log: log.clone(),
+            blobs: blobs.clone(),
+            job: job.clone(),
+        };
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let (stdout, stderr, status) = tokio::join!(
+            capture(stdout, &snapshot.job_id, "stdout", &log),
+            capture(stderr, &snapshot.job_id, "stderr", &log),
+            child.wait(),
+        );
+        let (stdout, stderr, status) = (stdout?, stderr?, status?);
+        process_group.0 = None;
+
+        if log.claim() {
+            log.upload(blobs.as_ref(), &job).await;
+        }
+
+        if !status.success() {
+            return Err(JobError::script(status.code(), &stderr).into());
         }
 
-        let temp_path = std::str::from_utf8(&output.stdout)?.trim().to_string();
-        debug!("Temporary file path: {}", temp_path);
+        let manifest = Manifest::from_stdout(&stdout)?;
+        for warning in &manifest.warnings {
+            warn!(
+                "Job {} ({}): {}",
+                snapshot.job_id,
+                analysis_job.type_name(),
+                warning
+            );
+        }
+        if !manifest.metrics.is_empty() {
+            info!(
+                "Job {} ({}) metrics: {}",
+                snapshot.job_id,
+                analysis_job.type_name(),
+                serde_json::to_string(&manifest.metrics)?
+            );
+        }
 
-        analysis_job.handle_result(job_id, &temp_path)?;
+        let artifacts = analysis_job
+            .handle_result(blobs.as_ref(), &snapshot, &manifest)
+            .await?;
+        job.lock().unwrap().artifacts.extend(artifacts);
 
         Ok(())
     })
 }
+
+/// Streams one of a script's output pipes into visiproc's log line by line, tagged with the job id,
+/// and appends it to the job log. Returns everything read from the pipe.
+async fn capture(
+    pipe: impl AsyncRead + Unpin,
+    job_id: &str,
+    pipe_name: &str,
+    job_log: &ScriptLog,
+) -> std::io::Result<String> {
+    let mut reader = BufReader::new(pipe);
+    let mut captured = String::new();
+    let mut line = Vec::new();
+
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        info!("[{} {}] {}", job_id, pipe_name, text);
+
+        captured.push_str(text);
+        captured.push('\n');
+        job_log.push_line(text);
+
+        line.clear();
+    }
+
+    Ok(captured)
+}
+
+/// Bucket and key prefix that a job's results are uploaded under, from its `s3_path`.
+fn result_location(job: &Job) -> Result<(String, String), JobError> {
+    let (bucket, prefix) = parse_s3_uri(&job.s3_path)
+        .map_err(|e| JobError::Permanent(format!("Invalid job s3 path: {}", e)))?;
+    let prefix = match prefix {
+        "" => String::new(),
+        p if p.ends_with('/') => p.to_string(),
+        p => format!("{}/", p),
+    };
+    Ok((bucket.to_string(), prefix))
+}
+
+/// Where a job's script writes its artifacts, relative to the working directory it runs in.
+fn output_dir(job: &Job) -> PathBuf {
+    Path::new("outputs").join(&job.request_id)
+}
+
+/// The key of an artifact below the job's results prefix: its path relative to `output_dir`, so
+/// files with the same name in different subdirectories are kept apart.
+fn artifact_key(output_dir: &Path, path: &Path) -> Result<String, JobError> {
+    let outside = || {
+        JobError::Permanent(format!(
+            "Artifact {} is not in the output directory {}",
+            path.display(),
+            output_dir.display()
+        ))
+    };
+    let absolute = |path: &Path| {
+        std::path::absolute(path)
+            .map_err(|e| JobError::Permanent(format!("Invalid artifact path: {}", e)))
+    };
+    let (output_dir, path) = (absolute(output_dir)?, absolute(path)?);
+    let relative = path.strip_prefix(&output_dir).map_err(|_| outside())?;
+
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().ok_or_else(outside)?),
+            _ => return Err(outside()),
+        }
+    }
+    if parts.is_empty() {
+        return Err(outside());
+    }
+    Ok(parts.join("/"))
+}
+
+/// The combined stdout and stderr of a script run, uploaded as `<job_id>.log` next to the job's
+/// results. Clones share the same log, so the queue can upload it after killing a run on timeout
+/// or cancellation; whoever claims it first uploads it.
+#[derive(Clone, Default)]
+pub struct ScriptLog {
+    lines: Arc<Mutex<String>>,
+    claimed: Arc<AtomicBool>, // set once someone is responsible for uploading the log
+}
+
+impl ScriptLog {
+    pub fn push_line(&self, text: &str) {
+        let mut lines = self.lines.lock().unwrap();
+        lines.push_str(text);
+        lines.push('\n');
+    }
+
+    /// Takes responsibility for uploading the log, returning false if it was already taken.
+    pub fn claim(&self) -> bool {
+        !self.claimed.swap(true, Ordering::SeqCst)
+    }
+
+    /// Uploads the log and records it on the job's artifacts. A failed upload is logged rather
+    /// than failing the job, the log is only there to help debugging.
+    pub async fn upload(&self, blobs: &dyn BlobStore, job: &Arc<Mutex<Job>>) {
+        let snapshot = job.lock().unwrap().clone();
+        let lines = self.lines.lock().unwrap().clone();
+        if let Some(log) = upload_log(blobs, &snapshot, lines).await {
+            let mut job = job.lock().unwrap();
+            // a retried run overwrites the log of the previous attempt
+            job.artifacts.retain(|artifact| artifact.uri != log.uri);
+            job.artifacts.push(log);
+        }
+    }
+}
+
+/// Uploads the log in the background when a run is dropped without anyone having claimed it,
+/// e.g. when the whole queue is dropped. Timed out and cancelled runs are uploaded by the queue.
+struct LogFallback {
+    log: ScriptLog,
+    blobs: Arc<dyn BlobStore>,
+    job: Arc<Mutex<Job>>,
+}
+
+impl Drop for LogFallback {
+    fn drop(&mut self) {
+        if !self.log.claim() {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (log, blobs, job) = (self.log.clone(), self.blobs.clone(), self.job.clone());
+            runtime.spawn(async move { log.upload(blobs.as_ref(), &job).await });
+        }
+    }
+}
+
+async fn upload_log(blobs: &dyn BlobStore, job: &Job, lines: String) -> Option<Artifact> {
+    let result = async {
+        let (bucket, prefix) = result_location(job)?;
+        let key = format!("{}{}.log", prefix, job.job_id);
+        blobs
+            .put_bytes(&bucket, &key, lines.into_bytes(), mime_type(&key))
+            .await?;
+        Ok::<_, eyre::Report>(Artifact {
+            kind: "log".to_string(),
+            uri: format!("s3://{}/{}", bucket, key),
+            mime_type: mime_type(&key).to_string(),
+            description: "Output of the analysis script".to_string(),
+        })
+    };
+
+    match result.await {
+        Ok(artifact) => Some(artifact),
+        Err(err) => {
+            warn!("Failed to upload the log of job {}: {err:#}", job.job_id);
+            None
+        }
+    }
+}
+
+/// Uploads every artifact in `manifest` under the job's `s3_path`, keyed by its path relative to
+/// the job's output directory.
+async fn upload_artifacts(
+    blobs: &dyn BlobStore,
+    job: &Job,
+    manifest: &Manifest,
+) -> Result<Vec<Artifact>> {
+    let (bucket, prefix) = result_location(job)?;
+    let output_dir = output_dir(job);
+
+    let mut artifacts = Vec::new();
+    for artifact in &manifest.artifacts {
+        let key = format!(
+            "{}{}",
+            prefix,
+            artifact_key(&output_dir, Path::new(&artifact.path))?
+        );
+
+        blobs
+            .put_file(
+                &bucket,
+                &key,
+                Path::new(&artifact.path),
+                &artifact.mime_type,
+            )
+            .await
+            .map_err(|e| JobError::Upload(format!("{}: {:#}", key, e)))?;
+        artifacts.push(Artifact {
+            kind: artifact.kind.clone(),
+            uri: format!("s3://{}/{}", bucket, key),
+            mime_type: artifact.mime_type.clone(),
+            description: artifact.description.clone(),
+        });
+    }
+
+    debug!(
+        "Uploaded {} result file(s) for job {}",
+        artifacts.len(),
+        job.job_id
+    );
+    Ok(artifacts)
+}
+
+/// Kills a script's process group when dropped before the script exits,
+/// i.e. when its run is timed out or cancelled.
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            debug!("Killing process group {}", pgid);
+            // SAFETY: killpg only sends a signal, an already exited group is reported through errno
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifacts_are_keyed_by_their_path_in_the_output_dir() {
+        let output_dir = Path::new("/work/outputs/abc");
+        let key = |path: &str| artifact_key(output_dir, Path::new(path));
+
+        assert_eq!(
+            key("/work/outputs/abc/abc-eda.html").unwrap(),
+            "abc-eda.html"
+        );
+        assert_eq!(key("/work/outputs/abc/./a/plot.png").unwrap(), "a/plot.png");
+        assert_eq!(key("/work/outputs/abc/b/plot.png").unwrap(), "b/plot.png");
+        for outside in [
+            "/work/outputs/abcd/plot.png",
+            "/work/outputs/abc/../plot.png",
+            "/work/outputs/abc",
+            "/tmp/plot.png",
+        ] {
+            assert!(
+                matches!(key(outside), Err(JobError::Permanent(_))),
+                "{outside}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn capture_collects_lines_into_the_job_log() {
+        let job_log = ScriptLog::default();
+        job_log.push_line("earlier");
+
+        let captured = capture(&b"first\r\nsecond\n\xffthird"[..], "1", "stdout", &job_log)
+            .await
+            .unwrap();
+
+        assert_eq!(captured, "first\nsecond\n\u{fffd}third\n");
+        assert_eq!(
+            *job_log.lines.lock().unwrap(),
+            "earlier\nfirst\nsecond\n\u{fffd}third\n"
+        );
+    }
+}
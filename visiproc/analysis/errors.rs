@@ -0,0 +1,115 @@
+use std::{error::Error, fmt};
+
+/// Exit code analysis scripts use to reject their input. Never worth retrying.
+pub const VALIDATION_EXIT_CODE: i32 = 2;
+
+/// How much of a failed script's stderr is kept, from its end, in the job's error and status
+/// history. The full output is in the job's log.
+pub const STDERR_TAIL_BYTES: usize = 4 * 1024;
+
+/// Failures raised while running an analysis job, typed so they can be classified for retries.
+#[derive(Debug)]
+pub enum JobError {
+    /// The analysis script exited unsuccessfully
+    Script { code: Option<i32>, stderr: String },
+    /// Uploading the job results failed
+    Upload(String),
+    /// A failure that retrying cannot fix
+    Permanent(String),
+    /// The run took longer than the job type's timeout and was killed
+    Timeout,
+    /// The run was cancelled and killed
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Script {
+                code: Some(code),
+                stderr,
+            } => write!(
+                f,
+                "Job analysis run failed (exit code {}): {}",
+                code, stderr
+            ),
+            JobError::Script { code: None, stderr } => {
+                write!(f, "Job analysis run killed by signal: {}", stderr)
+            }
+            JobError::Upload(message) => write!(f, "Job result upload failed: {}", message),
+            JobError::Permanent(message) => write!(f, "{}", message),
+            JobError::Timeout => write!(f, "timeout"),
+            JobError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+impl JobError {
+    /// A `Script` error keeping only the last `STDERR_TAIL_BYTES` of `stderr`, so a chatty script
+    /// cannot push the job record past the item size limit of the jobs table.
+    pub fn script(code: Option<i32>, stderr: &str) -> Self {
+        let stderr = if stderr.len() <= STDERR_TAIL_BYTES {
+            stderr.to_string()
+        } else {
+            let mut start = stderr.len() - STDERR_TAIL_BYTES;
+            while !stderr.is_char_boundary(start) {
+                start += 1;
+            }
+            format!("...{}", &stderr[start..])
+        };
+        JobError::Script { code, stderr }
+    }
+}
+
+/// Coarse categories of job failures that a `RetryPolicy` can opt into retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    Script,    // script exited with a non-zero code
+    Upload,    // result upload failed
+    Io,        // spawning the script or reading its output failed
+    Timeout,   // the run was killed for taking too long
+    Other,     // anything not raised as a `JobError`
+    Permanent, // never retried
+}
+
+impl ErrorClass {
+    /// Classifies `err`, treating script exits with any of `permanent_exit_codes` as permanent.
+    pub fn of(err: &eyre::Report, permanent_exit_codes: &[i32]) -> Self {
+        match err.downcast_ref::<JobError>() {
+            Some(JobError::Script {
+                code: Some(code), ..
+            }) if permanent_exit_codes.contains(code) => ErrorClass::Permanent,
+            Some(JobError::Script { .. }) => ErrorClass::Script,
+            Some(JobError::Upload(_)) => ErrorClass::Upload,
+            Some(JobError::Permanent(_)) | Some(JobError::Cancelled) => ErrorClass::Permanent,
+            Some(JobError::Timeout) => ErrorClass::Timeout,
+            None if err.downcast_ref::<std::io::Error>().is_some() => ErrorClass::Io,
+            None => ErrorClass::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_errors_keep_the_end_of_long_stderr() {
+        let short = JobError::script(Some(1), "Traceback: boom\n");
+        assert!(matches!(short, JobError::Script { stderr, .. } if stderr == "Traceback: boom\n"));
+
+        let long = format!(
+            "{}é\nValueError: bad input\n",
+            "progress 100%\n".repeat(1_000)
+        );
+        let JobError::Script { code, stderr } = JobError::script(Some(1), &long) else {
+            unreachable!()
+        };
+        assert_eq!(code, Some(1));
+        assert!(stderr.len() <= STDERR_TAIL_BYTES + 3);
+        assert!(stderr.starts_with("..."));
+        assert!(stderr.ends_with("ValueError: bad input\n"));
+    }
+}
@@ -0,0 +1,79 @@
+use super::errors::JobError;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A file produced by an analysis script, as listed in its manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ManifestArtifact {
+    pub kind: String,      // what the file holds, e.g. "report" or "data"
+    pub path: String,      // local path the script wrote it to
+    pub mime_type: String, // content type to upload it with
+    #[serde(default)]
+    pub description: String,
+}
+
+/// What a successful analysis script reports about its run, printed as a single JSON line on stdout:
+///
+/// `{"artifacts": [{"kind": "report", "path": "...", "mime_type": "text/html", "description": "..."}],
+///   "metrics": {"rows": 1000}, "warnings": []}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Manifest {
+    pub artifacts: Vec<ManifestArtifact>,
+    #[serde(default)]
+    pub metrics: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest from a script's stdout. It is the last line holding a JSON object,
+    /// so anything the script printed before it is ignored.
+    pub fn from_stdout(stdout: &str) -> Result<Self, JobError> {
+        let line = stdout
+            .lines()
+            .map(str::trim)
+            .rev()
+            .find(|line| line.starts_with('{'))
+            .ok_or_else(|| JobError::Permanent("Script printed no result manifest".to_string()))?;
+
+        serde_json::from_str(line)
+            .map_err(|e| JobError::Permanent(format!("Invalid result manifest: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_stdout_reads_the_last_json_line() {
+        let stdout = r#"
+            {"progress": "ignored, superseded by the manifest below"}
+            Summarize dataset: 100%
+            {"artifacts": [{"kind": "report", "path": "out/a-eda.html", "mime_type": "text/html"}], "warnings": ["few rows"]}
+        "#;
+
+        let manifest = Manifest::from_stdout(stdout).unwrap();
+        assert_eq!(
+            manifest.artifacts,
+            vec![ManifestArtifact {
+                kind: "report".to_string(),
+                path: "out/a-eda.html".to_string(),
+                mime_type: "text/html".to_string(),
+                description: String::new(),
+            }]
+        );
+        assert!(manifest.metrics.is_empty());
+        assert_eq!(manifest.warnings, vec!["few rows".to_string()]);
+    }
+
+    #[test]
+    fn from_stdout_rejects_missing_or_malformed_manifests() {
+        for stdout in ["", "/tmp/a-eda.html\n", "{\"artifacts\": \"a-eda.html\"}\n"] {
+            assert!(matches!(
+                Manifest::from_stdout(stdout),
+                Err(JobError::Permanent(_))
+            ));
+        }
+    }
+}
@@ -1 +1,4 @@
 pub mod analysis_jobs;
+pub mod errors;
+pub mod manifest;
+pub mod retry;
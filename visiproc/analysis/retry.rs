@@ -0,0 +1,127 @@
+use super::errors::{ErrorClass, VALIDATION_EXIT_CODE};
+use crate::models::job_type::JobType;
+use std::{
+    collections::{hash_map::RandomState, HashSet},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How often, and how patiently, a failed job of one type is retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,              // total runs, including the first
+    pub base_delay: Duration,           // delay before the first retry, doubled after each
+    pub max_delay: Duration,            // upper bound for the backoff delay
+    pub jitter: f64,                    // fraction of the delay to randomise, 0.0..=1.0
+    pub retryable: HashSet<ErrorClass>, // failures worth another attempt
+    pub permanent_exit_codes: Vec<i32>, // script exit codes that are never retried
+}
+
+impl RetryPolicy {
+    /// A policy that runs a job exactly once.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: 0.0,
+            retryable: HashSet::new(),
+            permanent_exit_codes: vec![VALIDATION_EXIT_CODE],
+        }
+    }
+
+    pub fn for_job_type(job_type: JobType) -> Self {
+        match job_type {
+            JobType::Eda | JobType::Corr => RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_secs(2),
+                max_delay: Duration::from_secs(30),
+                jitter: 0.2,
+                retryable: HashSet::from([ErrorClass::Script, ErrorClass::Upload, ErrorClass::Io]),
+                permanent_exit_codes: vec![VALIDATION_EXIT_CODE],
+            },
+            // the simulated jobs exist to exercise the happy and failure paths, retrying them only slows tests down
+            JobType::SimulatedJob | JobType::SimulatedError | JobType::None => RetryPolicy::never(),
+        }
+    }
+
+    /// Whether a job that has been run `attempts` times and failed with `err` should run again.
+    pub fn should_retry(&self, attempts: u32, err: &eyre::Report) -> bool {
+        attempts < self.max_attempts
+            && self
+                .retryable
+                .contains(&ErrorClass::of(err, &self.permanent_exit_codes))
+    }
+
+    /// Backoff before the next run of a job that has been run `attempts` times.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        // scale by a random factor in [1 - jitter, 1 + jitter]
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 + jitter * (2.0 * random_unit() - 1.0))
+    }
+}
+
+/// A random number in [0, 1], seeded from the per-process random hasher keys.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    hasher.finish() as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::errors::JobError;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::for_job_type(JobType::Eda)
+        }
+    }
+
+    fn script_error(code: i32) -> eyre::Report {
+        JobError::Script {
+            code: Some(code),
+            stderr: String::new(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max_delay() {
+        let policy = policy();
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(10), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn delay_stays_within_jitter_bounds() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy()
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn should_retry_respects_attempts_and_error_class() {
+        let policy = policy();
+        assert!(policy.should_retry(1, &script_error(1)));
+        assert!(!policy.should_retry(3, &script_error(1)));
+        assert!(!policy.should_retry(1, &script_error(VALIDATION_EXIT_CODE)));
+        assert!(!policy.should_retry(1, &JobError::Permanent("bad".into()).into()));
+        assert!(!policy.should_retry(1, &eyre::eyre!("unclassified")));
+    }
+}
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use crate::store::{QueryEngine, StoreFuture};
 use aws_config::SdkConfig;
 use aws_sdk_athena::{
     config::Builder,
@@ -7,6 +8,9 @@ use aws_sdk_athena::{
 };
 use eyre::{Error, Result};
 use log::debug;
+use std::{sync::Arc, time::Duration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn athena_client(conf: &SdkConfig) -> Client {
     let athena_config_builder = Builder::from(conf);
@@ -101,24 +105,35 @@ pub async fn get_query_results(
     Ok(rows)
 }
 
-pub async fn execute_ctas_query(
-    client: &Client,
+/// The CTAS statement creating `new_table` from the rows of `base_query`, stored as JSON under
+/// `external_location`.
+fn ctas_query(
     base_query: &str,
     new_table: &str,
     database: &str,
     external_location: &str,
-    output_location: &str,
-) -> Result<String, Error> {
+) -> String {
     // Example
     // let ctas_query = r#"CREATE TABLE "mockdata"."test_table_ctas_7"
     //                     WITH (external_location = 's3://metadata/test-jobID-7/')
     //                     AS SELECT * FROM mockdata.dataset1 LIMIT 2"#;
-    let ctas_query = format!(
+    format!(
         r#"CREATE TABLE "{}"."{}"
            WITH (format = 'JSON',  external_location = '{}')
            AS {}"#,
         database, new_table, external_location, base_query
-    );
+    )
+}
+
+pub async fn execute_ctas_query(
+    client: &Client,
+    base_query: &str,
+    new_table: &str,
+    database: &str,
+    external_location: &str,
+    output_location: &str,
+) -> Result<String, Error> {
+    let ctas_query = ctas_query(base_query, new_table, database, external_location);
 
     let response = client
         .start_query_execution()
@@ -140,11 +155,74 @@ pub async fn execute_ctas_query(
     }
 }
 
+/// Runs a CTAS query on `engine` to completion, see `ctas_query`.
+pub async fn create_table_as(
+    engine: &dyn QueryEngine,
+    base_query: &str,
+    new_table: &str,
+    database: &str,
+    external_location: &str,
+) -> Result<()> {
+    let query = ctas_query(base_query, new_table, database, external_location);
+    engine.query(&query, database).await?;
+    Ok(())
+}
+
+/// Whether `database` has a table named `table`.
+pub async fn table_exists(engine: &dyn QueryEngine, table: &str, database: &str) -> Result<bool> {
+    let rows = engine
+        .query(&format!("SHOW TABLES LIKE '{}'", table), database)
+        .await?;
+    Ok(rows.iter().flatten().any(|name| name == table))
+}
+
+/// Queries run by athena, writing their results under `output_location`.
+pub struct Athena {
+    client: Arc<Client>,
+    output_location: String,
+}
+
+impl Athena {
+    pub fn new(client: Arc<Client>, output_location: &str) -> Self {
+        Athena {
+            client,
+            output_location: output_location.to_string(),
+        }
+    }
+}
+
+impl QueryEngine for Athena {
+    fn query<'a>(&'a self, query: &'a str, database: &'a str) -> StoreFuture<'a, Vec<Vec<String>>> {
+        Box::pin(async move {
+            let query_execution_id =
+                start_query_execution(&self.client, query, database, &self.output_location).await?;
+            loop {
+                match check_query_execution_status(&self.client, &query_execution_id)
+                    .await?
+                    .as_str()
+                {
+                    "SUCCEEDED" => break,
+                    "FAILED" | "CANCELLED" => {
+                        return Err(eyre::eyre!(
+                            "Query {} failed or was cancelled",
+                            query_execution_id
+                        ))
+                    }
+                    _ => tokio::time::sleep(POLL_INTERVAL).await,
+                }
+            }
+            get_query_results(&self.client, &query_execution_id).await
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config;
-    use tokio::time::{sleep, Duration};
+    use crate::{
+        config::{configure, ConfigArgs},
+        store::memory::MemoryQueries,
+    };
     use uuid::Uuid;
 
     pub fn generate_uuid() -> String {
@@ -156,34 +234,67 @@ mod tests {
             .collect::<String>()
     }
 
+    async fn athena() -> Athena {
+        let (config, sdk_configs) = configure(&ConfigArgs::default(), None).await.unwrap();
+        Athena::new(
+            Arc::new(athena_client(&sdk_configs.athena)),
+            &format!("s3://{}", config.buckets.athena_output),
+        )
+    }
+
     #[tokio::test]
+    async fn ctas_queries_store_json_under_the_external_location() {
+        let queries = MemoryQueries::default();
+
+        create_table_as(
+            &queries,
+            "SELECT * FROM mockdata.dataset1 LIMIT 2",
+            "abc123",
+            "mockdata",
+            "s3://results/abc123/",
+        )
+        .await
+        .unwrap();
+
+        let run = queries.queries();
+        assert_eq!(run.len(), 1);
+        let (query, database) = &run[0];
+        assert_eq!(database, "mockdata");
+        assert!(
+            query.starts_with(r#"CREATE TABLE "mockdata"."abc123""#),
+            "{query}"
+        );
+        assert!(query.contains("format = 'JSON'"), "{query}");
+        assert!(
+            query.contains("external_location = 's3://results/abc123/'"),
+            "{query}"
+        );
+        assert!(
+            query.ends_with("AS SELECT * FROM mockdata.dataset1 LIMIT 2"),
+            "{query}"
+        );
+    }
+
+    #[tokio::test]
+    async fn tables_exist_when_show_tables_lists_them() {
+        let queries = MemoryQueries::new(vec![(
+            "SHOW TABLES LIKE 'abc123'",
+            vec![vec!["abc123".to_string()]],
+        )]);
+
+        assert!(table_exists(&queries, "abc123", "mockdata").await.unwrap());
+        assert!(!table_exists(&queries, "def456", "mockdata").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs localstack with the mockdata database"]
     async fn test_query_execution_and_fetch_results() {
-        let shared_config = config::configure().await.unwrap();
-        let client = athena_client(&shared_config);
-
-        let test_query = "SELECT * FROM mockdata.dataset1 LIMIT 2";
-        let database = "mockdata";
-        let output_location = "s3://aws-athena-query-results-000000000000-us-east-1";
-
-        let query_execution_id =
-            start_query_execution(&client, test_query, database, output_location)
-                .await
-                .expect("Failed to start query execution");
-
-        let mut status = "".to_string();
-        while status != "SUCCEEDED" {
-            sleep(Duration::from_secs(5)).await;
-            status = check_query_execution_status(&client, &query_execution_id)
-                .await
-                .expect("Failed to check query execution status");
-            if status == "FAILED" || status == "CANCELLED" {
-                panic!("Query execution failed or was cancelled");
-            }
-        }
+        let athena = athena().await;
 
-        let results = get_query_results(&client, &query_execution_id)
+        let results = athena
+            .query("SELECT * FROM mockdata.dataset1 LIMIT 2", "mockdata")
             .await
-            .expect("Failed to get query results");
+            .expect("Failed to run query");
 
         assert_eq!(
             results.len(),
@@ -193,56 +304,27 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs localstack with the mockdata database"]
     async fn test_ctas_execution_and_check_table_exists() {
-        let shared_config = config::configure().await.unwrap();
-        let client = athena_client(&shared_config);
+        let (config, _) = configure(&ConfigArgs::default(), None).await.unwrap();
+        let athena = athena().await;
 
         // we need a unqiue id for each CTAS query
         let uuid = generate_uuid();
+        let s3_external_location = format!("s3://{}/{}/", config.buckets.results, &uuid);
 
-        let base_query = "SELECT * FROM mockdata.dataset1 LIMIT 2";
-        let new_table_name = &uuid;
-        let database_name = "mockdata";
-        let s3_external_location = format!("s3://metadata/{}/", &uuid);
-        let output_location = "s3://aws-athena-query-results-000000000000-us-east-1";
-
-        let query_execution_id = execute_ctas_query(
-            &client,
-            base_query,
-            &new_table_name,
-            database_name,
+        create_table_as(
+            &athena,
+            "SELECT * FROM mockdata.dataset1 LIMIT 2",
+            &uuid,
+            "mockdata",
             &s3_external_location,
-            output_location,
         )
         .await
-        .expect("Failed to start query execution");
-
-        let mut status = "".to_string();
-        while status != "SUCCEEDED" {
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            status = check_query_execution_status(&client, &query_execution_id)
-                .await
-                .expect("Failed to check query execution status");
-            if status == "FAILED" || status == "CANCELLED" {
-                panic!("Query execution failed or was cancelled");
-            }
-        }
+        .expect("Failed to run CTAS query");
 
-        let check_table_query = format!("SHOW TABLES LIKE '{}'", new_table_name);
-        let check_query_execution_id =
-            start_query_execution(&client, &check_table_query, database_name, output_location)
-                .await
-                .expect("Failed to start table check query execution");
-
-        let mut check_status = "".to_string();
-        while check_status != "SUCCEEDED" {
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-            check_status = check_query_execution_status(&client, &check_query_execution_id)
-                .await
-                .expect("Failed to check table existence query execution status");
-            if check_status == "FAILED" || check_status == "CANCELLED" {
-                panic!("Table existence query execution failed or was cancelled");
-            }
-        }
+        assert!(table_exists(&athena, &uuid, "mockdata")
+            .await
+            .expect("Failed to check the table exists"));
     }
 }
@@ -1,10 +1,22 @@
 #![allow(dead_code)]
+use crate::{
+    aws::item::{from_item, to_item},
+    models::{job::Job, job_request::JobRequest, job_response::JobResponse, status::Status},
+    store::{JobStore, RequestStore, StoreFuture},
+};
 use aws_config::SdkConfig;
 use aws_sdk_dynamodb::{
-    config::Builder, operation::delete_item::DeleteItemOutput, types::AttributeValue, Client, Error,
+    config::Builder,
+    operation::{
+        delete_item::DeleteItemOutput, put_item::PutItemOutput, update_item::UpdateItemError,
+    },
+    types::{AttributeValue, ReturnValue},
+    Client, Error,
 };
 use eyre::Result;
 use log::debug;
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, sync::Arc};
 
 pub fn dynamodb_client(conf: &SdkConfig) -> Client {
     let dynamodb_config_builder = Builder::from(conf);
@@ -61,3 +73,254 @@ pub async fn delete_item(
         Err(e) => Err(e.into()),
     }
 }
+
+pub async fn put_item(
+    client: &Client,
+    table: &str,
+    item: HashMap<String, AttributeValue>,
+) -> Result<PutItemOutput> {
+    let out = client
+        .put_item()
+        .table_name(table)
+        .set_item(Some(item))
+        .send()
+        .await?;
+    debug!("Put item into {}", table);
+
+    Ok(out)
+}
+
+/// Every item of `table`, or of its `index` when given, whose key attribute `key` equals `value`,
+/// reading all pages of the result.
+pub async fn query_for(
+    client: &Client,
+    table: &str,
+    index: Option<&str>,
+    key: &str,
+    value: AttributeValue,
+) -> Result<Vec<HashMap<String, AttributeValue>>> {
+    let items = client
+        .query()
+        .table_name(table)
+        .set_index_name(index.map(str::to_string))
+        .key_condition_expression("#k = :val")
+        .expression_attribute_names("#k", key)
+        .expression_attribute_values(":val", value)
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await?;
+
+    Ok(items)
+}
+
+/// Sets the status of `request` if its stored status is still `request.status`, returning the
+/// item as stored after the update, or `None` when the condition failed.
+async fn set_request_status(
+    client: &Client,
+    table: &str,
+    request: &JobRequest,
+    new_status: &Status,
+) -> Result<Option<HashMap<String, AttributeValue>>> {
+    let result = client
+        .update_item()
+        .table_name(table)
+        .key("requestID", AttributeValue::S(request.request_id.clone()))
+        .key(
+            "creationDate",
+            AttributeValue::N(request.timestamp.to_string()),
+        )
+        .update_expression("SET #st = :status_val")
+        .condition_expression("#st = :expected")
+        .expression_attribute_names("#st", "jobStatus")
+        .expression_attribute_values(":status_val", AttributeValue::S(new_status.to_string()))
+        .expression_attribute_values(":expected", AttributeValue::S(request.status.to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+
+    match result {
+        Ok(out) => out.attributes.map(Some).ok_or_else(|| {
+            eyre::eyre!("No attributes returned for request {}", request.request_id)
+        }),
+        Err(e)
+            if matches!(
+                e.as_service_error(),
+                Some(UpdateItemError::ConditionalCheckFailedException(_))
+            ) =>
+        {
+            debug!(
+                "Request {} is no longer {}, not setting it to {}",
+                request.request_id, request.status, new_status
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The jobs of `worker_id` that are QUEUED, PROCESSING or RETRYING, with a query per status on
+/// the `worker_index` GSI of the jobs table, keyed by workerID and jobStatus.
+async fn query_unfinished_jobs(
+    client: &Client,
+    table: &str,
+    worker_index: &str,
+    worker_id: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>> {
+    let mut items = Vec::new();
+    for status in [Status::Queued, Status::Processing, Status::Retrying] {
+        let found = client
+            .query()
+            .table_name(table)
+            .index_name(worker_index)
+            .key_condition_expression("#w = :worker AND #st = :status")
+            .expression_attribute_names("#w", "workerID")
+            .expression_attribute_names("#st", "jobStatus")
+            .expression_attribute_values(":worker", AttributeValue::S(worker_id.to_string()))
+            .expression_attribute_values(":status", AttributeValue::S(status.to_string()))
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+        items.extend(found);
+    }
+
+    Ok(items)
+}
+
+fn read_item<T: DeserializeOwned>(
+    table: &str,
+    item: &HashMap<String, AttributeValue>,
+) -> Result<T> {
+    from_item(item).map_err(|e| eyre::eyre!("Invalid item in {}: {}", table, e))
+}
+
+/// Reads every item of `table` as a `T`, failing on the first one that does not match.
+fn read_items<T: DeserializeOwned>(
+    table: &str,
+    items: &[HashMap<String, AttributeValue>],
+) -> Result<Vec<T>> {
+    items.iter().map(|item| read_item(table, item)).collect()
+}
+
+/// The requests table, queried by status through its `status_index` GSI.
+pub struct DynamoDbRequests {
+    client: Arc<Client>,
+    table: String,
+    status_index: String,
+}
+
+impl DynamoDbRequests {
+    pub fn new(client: Arc<Client>, table: &str, status_index: &str) -> Self {
+        DynamoDbRequests {
+            client,
+            table: table.to_string(),
+            status_index: status_index.to_string(),
+        }
+    }
+
+    async fn query(&self, index: Option<&str>, key: &str, value: &str) -> Result<Vec<JobRequest>> {
+        let value = AttributeValue::S(value.to_string());
+        let items = query_for(&self.client, &self.table, index, key, value).await?;
+        read_items(&self.table, &items)
+    }
+}
+
+impl RequestStore for DynamoDbRequests {
+    fn requests_with_status(&self, status: Status) -> StoreFuture<'_, Vec<JobRequest>> {
+        Box::pin(async move {
+            let index = Some(self.status_index.as_str());
+            self.query(index, "jobStatus", &status.to_string()).await
+        })
+    }
+
+    fn requests_with_id<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<JobRequest>> {
+        Box::pin(self.query(None, "requestID", request_id))
+    }
+
+    fn set_status<'a>(
+        &'a self,
+        request: &'a JobRequest,
+        status: Status,
+    ) -> StoreFuture<'a, Option<JobRequest>> {
+        Box::pin(async move {
+            set_request_status(&self.client, &self.table, request, &status)
+                .await?
+                .map(|item| read_item(&self.table, &item))
+                .transpose()
+        })
+    }
+}
+
+/// The jobs and job responses tables, with the jobs of a request found through the
+/// `request_index` GSI of the jobs table, and those of a worker through its `worker_index` GSI.
+pub struct DynamoDbJobs {
+    client: Arc<Client>,
+    jobs_table: String,
+    request_index: String,
+    worker_index: String,
+    responses_table: String,
+}
+
+impl DynamoDbJobs {
+    pub fn new(
+        client: Arc<Client>,
+        jobs_table: &str,
+        request_index: &str,
+        worker_index: &str,
+        responses_table: &str,
+    ) -> Self {
+        DynamoDbJobs {
+            client,
+            jobs_table: jobs_table.to_string(),
+            request_index: request_index.to_string(),
+            worker_index: worker_index.to_string(),
+            responses_table: responses_table.to_string(),
+        }
+    }
+}
+
+impl JobStore for DynamoDbJobs {
+    fn put_job<'a>(&'a self, job: &'a Job) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            put_item(&self.client, &self.jobs_table, to_item(job)?).await?;
+            Ok(())
+        })
+    }
+
+    fn unfinished_jobs<'a>(&'a self, worker_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+        Box::pin(async move {
+            let items = query_unfinished_jobs(
+                &self.client,
+                &self.jobs_table,
+                &self.worker_index,
+                worker_id,
+            )
+            .await?;
+            read_items(&self.jobs_table, &items)
+        })
+    }
+
+    fn request_jobs<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+        Box::pin(async move {
+            let items = query_for(
+                &self.client,
+                &self.jobs_table,
+                Some(&self.request_index),
+                "requestID",
+                AttributeValue::S(request_id.to_string()),
+            )
+            .await?;
+            read_items(&self.jobs_table, &items)
+        })
+    }
+
+    fn put_response<'a>(&'a self, response: &'a JobResponse) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            put_item(&self.client, &self.responses_table, to_item(response)?).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,681 @@
+//! Maps serde types to and from DynamoDB items, so a model only names its attributes with
+//! `#[serde(rename = "...")]` instead of converting every field by hand.
+//!
+//! Strings, numbers, booleans, sequences and maps or structs are stored as `S`, `N`, `BOOL`, `L`
+//! and `M`. `None` struct fields are left out of the item, and missing attributes read as `None`.
+//! Unit enum variants are stored by name in an `S`; variants carrying data are not supported.
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use serde::{
+    de::{self, value::SeqDeserializer, DeserializeOwned, IntoDeserializer, Visitor},
+    forward_to_deserialize_any,
+    ser::{self, Impossible, Serialize},
+};
+use std::{collections::HashMap, fmt};
+
+pub type Item = HashMap<String, AttributeValue>;
+
+/// A failure to map an item, naming the attribute it happened at, e.g. `artifacts[0].mimeType`.
+#[derive(Debug)]
+pub struct ItemError {
+    path: Vec<String>, // innermost segment first
+    message: String,
+}
+
+impl ItemError {
+    fn new(message: impl fmt::Display) -> Self {
+        ItemError {
+            path: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Records that the error happened inside the attribute or list index `segment`.
+    fn at(mut self, segment: String) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in self.path.iter().rev() {
+            if !path.is_empty() && !segment.starts_with('[') {
+                path.push('.');
+            }
+            path.push_str(segment);
+        }
+        path
+    }
+}
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path(), self.message)
+        }
+    }
+}
+
+impl std::error::Error for ItemError {}
+
+impl ser::Error for ItemError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ItemError::new(msg)
+    }
+}
+
+impl de::Error for ItemError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ItemError::new(msg)
+    }
+}
+
+pub fn to_item<T: Serialize + ?Sized>(value: &T) -> Result<Item, ItemError> {
+    match value.serialize(AttributeSerializer)? {
+        AttributeValue::M(item) => Ok(item),
+        _ => Err(ItemError::new(
+            "only structs and maps can be stored as items",
+        )),
+    }
+}
+
+pub fn from_item<T: DeserializeOwned>(item: &Item) -> Result<T, ItemError> {
+    T::deserialize(ItemDeserializer(item))
+}
+
+struct AttributeSerializer;
+
+fn number(value: impl ToString) -> Result<AttributeValue, ItemError> {
+    Ok(AttributeValue::N(value.to_string()))
+}
+
+fn unsupported_variant(name: &str, variant: &str) -> ItemError {
+    ItemError::new(format!(
+        "enum variant {}::{} carries data, only unit variants can be stored",
+        name, variant
+    ))
+}
+
+impl ser::Serializer for AttributeSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = Impossible<AttributeValue, ItemError>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = Impossible<AttributeValue, ItemError>;
+
+    fn serialize_bool(self, v: bool) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<AttributeValue, ItemError> {
+        number(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::S(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::S(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::B(Blob::new(v)))
+    }
+
+    fn serialize_none(self) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::Null(true))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<AttributeValue, ItemError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::Null(true))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::Null(true))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::S(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<AttributeValue, ItemError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> Result<AttributeValue, ItemError> {
+        Err(unsupported_variant(name, variant))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer, ItemError> {
+        Ok(ListSerializer {
+            values: Vec::with_capacity(len.unwrap_or_default()),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer, ItemError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ListSerializer, ItemError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, ItemError> {
+        Err(unsupported_variant(name, variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, ItemError> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<MapSerializer, ItemError> {
+        Ok(MapSerializer::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, ItemError> {
+        Err(unsupported_variant(name, variant))
+    }
+}
+
+struct ListSerializer {
+    values: Vec<AttributeValue>,
+}
+
+impl ser::SerializeSeq for ListSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        let index = self.values.len();
+        let value = value
+            .serialize(AttributeSerializer)
+            .map_err(|e| e.at(format!("[{}]", index)))?;
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::L(self.values))
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<AttributeValue, ItemError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<AttributeValue, ItemError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+#[derive(Default)]
+struct MapSerializer {
+    map: Item,
+    key: Option<String>, // key of the value serialized next
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ItemError> {
+        self.key = match key.serialize(AttributeSerializer)? {
+            AttributeValue::S(key) | AttributeValue::N(key) => Some(key),
+            _ => return Err(ItemError::new("map keys must be strings or numbers")),
+        };
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ItemError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ItemError::new("map value serialized before its key"))?;
+        let value = value
+            .serialize(AttributeSerializer)
+            .map_err(|e| e.at(key.clone()))?;
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::M(self.map))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = AttributeValue;
+    type Error = ItemError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ItemError> {
+        let value = value
+            .serialize(AttributeSerializer)
+            .map_err(|e| e.at(key.to_string()))?;
+        // absent attributes read back as None, so there is no need to store nulls
+        if !value.is_null() {
+            self.map.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<AttributeValue, ItemError> {
+        Ok(AttributeValue::M(self.map))
+    }
+}
+
+struct ItemDeserializer<'a>(&'a Item);
+
+impl<'de, 'a> de::Deserializer<'de> for ItemDeserializer<'a> {
+    type Error = ItemError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+        visitor.visit_map(MapAccess::new(self.0))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct AttributeDeserializer<'a>(&'a AttributeValue);
+
+fn visit_number<'de, V: Visitor<'de>>(n: &str, visitor: V) -> Result<V::Value, ItemError> {
+    if let Ok(n) = n.parse::<i64>() {
+        visitor.visit_i64(n)
+    } else if let Ok(n) = n.parse::<u64>() {
+        visitor.visit_u64(n)
+    } else if let Ok(n) = n.parse::<f64>() {
+        visitor.visit_f64(n)
+    } else {
+        Err(ItemError::new(format!("invalid number `{}`", n)))
+    }
+}
+
+fn visit_list<'de, V: Visitor<'de>>(
+    values: &[AttributeValue],
+    visitor: V,
+) -> Result<V::Value, ItemError> {
+    visitor.visit_seq(ListAccess {
+        values: values.iter(),
+        index: 0,
+    })
+}
+
+/// How `value` is described in type errors, e.g. "a number (N)".
+fn unexpected(value: &AttributeValue) -> de::Unexpected<'static> {
+    let description = match value {
+        AttributeValue::S(_) => "a string (S)",
+        AttributeValue::N(_) => "a number (N)",
+        AttributeValue::Bool(_) => "a boolean (BOOL)",
+        AttributeValue::Null(_) => "a null (NULL)",
+        AttributeValue::L(_) => "a list (L)",
+        AttributeValue::M(_) => "a map (M)",
+        AttributeValue::Ss(_) => "a string set (SS)",
+        AttributeValue::Ns(_) => "a number set (NS)",
+        AttributeValue::B(_) => "a binary (B)",
+        AttributeValue::Bs(_) => "a binary set (BS)",
+        _ => "an unknown attribute type",
+    };
+    de::Unexpected::Other(description)
+}
+
+impl<'de, 'a> de::Deserializer<'de> for AttributeDeserializer<'a> {
+    type Error = ItemError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::S(s) => visitor.visit_str(s),
+            AttributeValue::N(n) => visit_number(n, visitor),
+            AttributeValue::Bool(b) => visitor.visit_bool(*b),
+            AttributeValue::Null(_) => visitor.visit_unit(),
+            AttributeValue::L(values) => visit_list(values, visitor),
+            AttributeValue::M(map) => visitor.visit_map(MapAccess::new(map)),
+            AttributeValue::Ss(strings) => {
+                let mut seq = SeqDeserializer::new(strings.iter().map(String::as_str));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            AttributeValue::Ns(numbers) => {
+                let numbers: Vec<_> = numbers.iter().cloned().map(AttributeValue::N).collect();
+                visit_list(&numbers, visitor)
+            }
+            AttributeValue::B(blob) => visitor.visit_bytes(blob.as_ref()),
+            AttributeValue::Bs(blobs) => {
+                let blobs: Vec<_> = blobs.iter().cloned().map(AttributeValue::B).collect();
+                visit_list(&blobs, visitor)
+            }
+            value => Err(de::Error::invalid_type(unexpected(value), &visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::Null(_) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ItemError> {
+        match self.0 {
+            AttributeValue::S(variant) => visitor.visit_enum(variant.as_str().into_deserializer()),
+            value => Err(de::Error::invalid_type(unexpected(value), &visitor)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct ListAccess<'a> {
+    values: std::slice::Iter<'a, AttributeValue>,
+    index: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for ListAccess<'a> {
+    type Error = ItemError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ItemError> {
+        let Some(value) = self.values.next() else {
+            return Ok(None);
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(AttributeDeserializer(value))
+            .map(Some)
+            .map_err(|e| e.at(format!("[{}]", index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct MapAccess<'a> {
+    entries: std::collections::hash_map::Iter<'a, String, AttributeValue>,
+    value: Option<(&'a String, &'a AttributeValue)>, // entry whose key was read last
+}
+
+impl<'a> MapAccess<'a> {
+    fn new(map: &'a Item) -> Self {
+        MapAccess {
+            entries: map.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapAccess<'a> {
+    type Error = ItemError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ItemError> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some((key, value));
+        seed.deserialize(key.as_str().into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ItemError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| ItemError::new("map value read before its key"))?;
+        seed.deserialize(AttributeDeserializer(value))
+            .map_err(|e| e.at(key.clone()))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Round,
+        Square,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Part {
+        name: String,
+        #[serde(rename = "sizeMm")]
+        size: f64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Widget {
+        #[serde(rename = "widgetID")]
+        id: String,
+        count: u32,
+        offset: i64,
+        enabled: bool,
+        shape: Shape,
+        note: Option<String>,
+        tags: Vec<String>,
+        parts: Vec<Part>,
+        labels: HashMap<String, i32>,
+    }
+
+    fn widget() -> Widget {
+        Widget {
+            id: "w-1".to_string(),
+            count: 3,
+            offset: -7,
+            enabled: true,
+            shape: Shape::Square,
+            note: None,
+            tags: vec!["a".to_string(), "b".to_string()],
+            parts: vec![Part {
+                name: "bolt".to_string(),
+                size: 2.5,
+            }],
+            labels: HashMap::from([("x".to_string(), 1)]),
+        }
+    }
+
+    #[test]
+    fn structs_round_trip() {
+        let item = to_item(&widget()).unwrap();
+
+        assert_eq!(item["widgetID"], AttributeValue::S("w-1".to_string()));
+        assert_eq!(item["count"], AttributeValue::N("3".to_string()));
+        assert_eq!(item["offset"], AttributeValue::N("-7".to_string()));
+        assert_eq!(item["enabled"], AttributeValue::Bool(true));
+        assert_eq!(item["shape"], AttributeValue::S("Square".to_string()));
+        assert!(!item.contains_key("note"));
+        assert_eq!(
+            item["parts"],
+            AttributeValue::L(vec![AttributeValue::M(HashMap::from([
+                ("name".to_string(), AttributeValue::S("bolt".to_string())),
+                ("sizeMm".to_string(), AttributeValue::N("2.5".to_string())),
+            ]))])
+        );
+        assert_eq!(from_item::<Widget>(&item).unwrap(), widget());
+
+        let noted = Widget {
+            note: Some("fragile".to_string()),
+            ..widget()
+        };
+        assert_eq!(
+            from_item::<Widget>(&to_item(&noted).unwrap()).unwrap(),
+            noted
+        );
+    }
+
+    #[test]
+    fn sets_read_as_lists() {
+        let mut item = to_item(&widget()).unwrap();
+        item.insert(
+            "tags".to_string(),
+            AttributeValue::Ss(vec!["c".to_string()]),
+        );
+        assert_eq!(from_item::<Widget>(&item).unwrap().tags, vec!["c"]);
+    }
+
+    #[test]
+    fn errors_name_the_attribute_and_the_expected_type() {
+        let mut item = to_item(&widget()).unwrap();
+        item.insert("count".to_string(), AttributeValue::S("3".to_string()));
+        let err = from_item::<Widget>(&item).unwrap_err();
+        assert_eq!(err.path(), "count");
+        assert!(err.to_string().contains("expected u32"), "{err}");
+
+        let mut item = to_item(&widget()).unwrap();
+        item.insert(
+            "parts".to_string(),
+            AttributeValue::L(vec![AttributeValue::M(HashMap::from([(
+                "name".to_string(),
+                AttributeValue::N("1".to_string()),
+            )]))]),
+        );
+        let err = from_item::<Widget>(&item).unwrap_err();
+        assert_eq!(err.path(), "parts[0].name");
+        assert!(err.to_string().contains("expected a string"), "{err}");
+
+        let mut item = to_item(&widget()).unwrap();
+        item.remove("widgetID");
+        let err = from_item::<Widget>(&item).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `widgetID`");
+
+        let mut item = to_item(&widget()).unwrap();
+        item.insert("shape".to_string(), AttributeValue::Bool(true));
+        let err = from_item::<Widget>(&item).unwrap_err();
+        assert_eq!(err.path(), "shape");
+        assert!(err.to_string().contains("a boolean (BOOL)"), "{err}");
+    }
+}
@@ -1,5 +1,6 @@
 pub mod athena;
 pub mod dynamodb;
+pub mod item;
 pub mod s3;
 pub mod sns;
 pub mod sqs;
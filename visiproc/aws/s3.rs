@@ -1,7 +1,15 @@
 #![allow(dead_code)]
+use crate::store::{BlobStore, StoreFuture};
+use bytes::Bytes;
 use eyre::Result;
+use futures::{future::BoxFuture, stream, FutureExt, Stream};
 use log::{debug, error};
-use std::{path::Path, time::Duration};
+use parquet::{
+    arrow::async_reader::{fetch_parquet_metadata, AsyncFileReader},
+    errors::ParquetError,
+    file::metadata::ParquetMetaData,
+};
+use std::{ops::Range, path::Path, sync::Arc, time::Duration};
 
 use aws_config::SdkConfig;
 use aws_sdk_s3::{
@@ -10,15 +18,16 @@ use aws_sdk_s3::{
     operation::{
         copy_object::{CopyObjectError, CopyObjectOutput},
         get_object::{GetObjectError, GetObjectOutput},
-        // put_object::{PutObjectError, PutObjectOutput},
+        put_object::PutObjectOutput,
     },
     presigning::PresigningConfig,
     primitives::ByteStream,
     Client,
 };
 
-pub fn s3_client(conf: &SdkConfig) -> Client {
-    let s3_config_builder = Builder::from(conf);
+/// `force_path_style` addresses buckets by path rather than by subdomain, as localstack expects.
+pub fn s3_client(conf: &SdkConfig, force_path_style: bool) -> Client {
+    let s3_config_builder = Builder::from(conf).force_path_style(force_path_style);
     Client::from_conf(s3_config_builder.build())
 }
 
@@ -64,6 +73,39 @@ pub async fn list_objects(client: &Client, bucket: &str) -> Result<Vec<String>>
     Ok(object_keys)
 }
 
+/// Every key in `bucket` that starts with `prefix`, in key order.
+pub async fn list_objects_with_prefix(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+
+    let mut object_keys = Vec::new();
+    while let Some(page) = pages.next().await {
+        object_keys.extend(
+            page?
+                .contents()
+                .iter()
+                .filter_map(|object| object.key().map(String::from)),
+        );
+    }
+
+    debug!(
+        "Found {} objects under s3://{}/{}",
+        object_keys.len(),
+        bucket,
+        prefix
+    );
+
+    Ok(object_keys)
+}
+
 async fn put_object(client: &Client, bucket: &str, object: &str, expires_in: u64) -> Result<()> {
     let expires_in = Duration::from_secs(expires_in);
 
@@ -126,52 +168,254 @@ pub async fn download_object(
         .await
 }
 
-pub async fn upload_object(client: &Client, bucket: &str, filename: &str, key: &str) -> Result<()> {
+/// Size in bytes of `s3://bucket/key`.
+pub async fn object_size(client: &Client, bucket: &str, key: &str) -> Result<usize> {
+    let resp = client.head_object().bucket(bucket).key(key).send().await?;
+    let size = resp
+        .content_length()
+        .ok_or_else(|| eyre::eyre!("No content length for s3://{}/{}", bucket, key))?;
+    Ok(usize::try_from(size)?)
+}
+
+/// Downloads the bytes of `s3://bucket/key` in `range` with a single range request.
+pub async fn get_range(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    range: Range<usize>,
+) -> Result<Bytes> {
+    if range.is_empty() {
+        return Ok(Bytes::new());
+    }
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes={}-{}", range.start, range.end - 1))
+        .send()
+        .await?;
+    let body = resp.body.collect().await?.into_bytes();
+
+    debug!(
+        "Fetched bytes {}..{} of s3://{}/{}",
+        range.start, range.end, bucket, key
+    );
+
+    Ok(body)
+}
+
+/// Streams the body of `s3://bucket/key` chunk by chunk as it downloads.
+pub async fn stream_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<impl Stream<Item = Result<Bytes>> + Send + Unpin + 'static> {
+    let body = download_object(client, bucket, key).await?.body;
+    Ok(Box::pin(stream::try_unfold(body, |mut body| async move {
+        Ok(body.try_next().await?.map(|chunk| (chunk, body)))
+    })))
+}
+
+/// Reads a Parquet object through range requests, so only its footer and the column chunks
+/// of the row groups being read are downloaded.
+pub struct S3ObjectReader {
+    client: Client,
+    bucket: String,
+    key: String,
+    size: usize,
+}
+
+impl S3ObjectReader {
+    pub async fn new(client: &Client, uri: &str) -> Result<Self> {
+        let (bucket, key) = parse_s3_uri(uri)?;
+        let size = object_size(client, bucket, key).await?;
+        Ok(S3ObjectReader {
+            client: client.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            size,
+        })
+    }
+}
+
+impl AsyncFileReader for S3ObjectReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, parquet::errors::Result<Bytes>> {
+        get_range(&self.client, &self.bucket, &self.key, range)
+            .map(|result| result.map_err(|err| ParquetError::External(err.into())))
+            .boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, parquet::errors::Result<Arc<ParquetMetaData>>> {
+        async move {
+            let metadata = fetch_parquet_metadata(
+                |range| {
+                    get_range(&self.client, &self.bucket, &self.key, range)
+                        .map(|result| result.map_err(|err| ParquetError::External(err.into())))
+                },
+                self.size,
+                None,
+            )
+            .await?;
+            Ok(Arc::new(metadata))
+        }
+        .boxed()
+    }
+}
+
+/// Splits an `s3://bucket/key` uri into its bucket and key.
+pub fn parse_s3_uri(uri: &str) -> Result<(&str, &str)> {
+    let path = uri
+        .strip_prefix("s3://")
+        .ok_or_else(|| eyre::eyre!("Not an s3 uri: {}", uri))?;
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    if bucket.is_empty() {
+        return Err(eyre::eyre!("Missing bucket in s3 uri: {}", uri));
+    }
+    Ok((bucket, key))
+}
+
+pub fn mime_type(filename: &str) -> &'static str {
     let extension = Path::new(filename)
         .extension()
         .and_then(|e| e.to_str())
         .unwrap_or_default();
 
-    let mime_type = match extension {
+    match extension {
         "html" => "text/html",
         "log" => "text/html", // assuming this also has to be text/html to be rendered in iframe
         "png" => "image/png",
         "txt" => "text/plain",
-        // "parquet" => "application/parquet", // unneeded?
+        "json" => "application/json",
+        "csv" => "text/csv",
+        "parquet" => "application/vnd.apache.parquet",
         _ => "application/octet-stream", // Default binary MIME type
-    };
+    }
+}
 
-    let resp = client.list_buckets().send().await?;
+/// Uploads the local file `filename` to `s3://bucket/key`, with a content type matching its extension.
+pub async fn upload_object(
+    client: &Client,
+    bucket: &str,
+    filename: &str,
+    key: &str,
+) -> Result<PutObjectOutput> {
+    upload_object_as(
+        client,
+        bucket,
+        Path::new(filename),
+        key,
+        mime_type(filename),
+    )
+    .await
+}
 
-    for bucket in resp.buckets() {
-        println!("bucket: {:#?}", bucket.name().unwrap_or_default())
-    }
+/// Uploads the local file at `path` to `s3://bucket/key` with the given content type.
+pub async fn upload_object_as(
+    client: &Client,
+    bucket: &str,
+    path: &Path,
+    key: &str,
+    mime_type: &str,
+) -> Result<PutObjectOutput> {
+    let body = ByteStream::from_path(path).await?;
 
-    println!();
+    let resp = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .content_type(mime_type)
+        .body(body)
+        .send()
+        .await?;
 
-    let body = ByteStream::from_path(Path::new(filename)).await;
+    debug!("Uploaded {} to s3://{}/{}", path.display(), bucket, key);
 
-    match body {
-        Ok(b) => {
-            let resp = client
-                .put_object()
-                .bucket(bucket)
-                .key(key)
-                .content_type(mime_type)
-                .body(b)
-                .send()
-                .await?;
+    Ok(resp)
+}
 
-            println!("Upload success. Version: {:#?}", resp.version_id);
+/// Uploads `body` to `s3://bucket/key` with the given content type.
+pub async fn upload_bytes(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    body: Vec<u8>,
+    mime_type: &str,
+) -> Result<PutObjectOutput> {
+    let resp = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .content_type(mime_type)
+        .body(ByteStream::from(body))
+        .send()
+        .await?;
 
-            let resp = client.get_object().bucket(bucket).key(key).send().await?;
-            let data = resp.body.collect().await;
-            println!("data: {:#?}", data.unwrap().into_bytes());
-        }
-        Err(err) => {
-            error!("{err:#?}")
-        }
+    debug!("Uploaded s3://{}/{}", bucket, key);
+
+    Ok(resp)
+}
+
+/// Job inputs and results in S3.
+pub struct S3Blobs {
+    client: Arc<Client>,
+}
+
+impl S3Blobs {
+    pub fn new(client: Arc<Client>) -> Self {
+        S3Blobs { client }
     }
+}
 
-    Ok(())
+impl BlobStore for S3Blobs {
+    fn put_file<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        path: &'a Path,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            upload_object_as(&self.client, bucket, path, key, mime_type).await?;
+            Ok(())
+        })
+    }
+
+    fn put_bytes<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        body: Vec<u8>,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            upload_bytes(&self.client, bucket, key, body, mime_type).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_s3_uri_splits_bucket_and_key() {
+        assert_eq!(
+            parse_s3_uri("s3://metadata/abc/").unwrap(),
+            ("metadata", "abc/")
+        );
+        assert_eq!(parse_s3_uri("s3://metadata").unwrap(), ("metadata", ""));
+        assert!(parse_s3_uri("metadata/abc/").is_err());
+        assert!(parse_s3_uri("s3:///abc").is_err());
+    }
+
+    #[test]
+    fn mime_type_follows_extension() {
+        assert_eq!(mime_type("/tmp/abc-eda.html"), "text/html");
+        assert_eq!(
+            mime_type("/tmp/abc-data.parquet"),
+            "application/vnd.apache.parquet"
+        );
+        assert_eq!(mime_type("/tmp/abc"), "application/octet-stream");
+    }
 }
@@ -1,7 +1,9 @@
+use crate::store::{EventPublisher, StoreFuture};
 use aws_config::SdkConfig;
 use aws_sdk_sns::{config::Builder, types::Topic, Client};
 use eyre::{Error, Result};
 use log::debug;
+use std::sync::Arc;
 
 pub fn sns_client(conf: &SdkConfig) -> Client {
     let sns_config_builder = Builder::from(conf);
@@ -59,6 +61,27 @@ pub async fn publish(client: &Client, topic_arn: &str, message: &str) -> Result<
     Ok(())
 }
 
+/// Request updates published to SNS topics.
+pub struct SnsPublisher {
+    client: Arc<Client>,
+}
+
+impl SnsPublisher {
+    pub fn new(client: Arc<Client>) -> Self {
+        SnsPublisher { client }
+    }
+}
+
+impl EventPublisher for SnsPublisher {
+    fn topics(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(list_topics(&self.client))
+    }
+
+    fn publish<'a>(&'a self, topic: &'a str, message: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(publish(&self.client, topic, message))
+    }
+}
+
 pub async fn _setup_topic(client: &Client) -> Result<()> {
     // leaving this here for now
     let topic_arn = "arn:aws:sns:us-east-1:000000000000:requestUpdatesTopic.fifo".to_string();
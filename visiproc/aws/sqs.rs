@@ -1,9 +1,18 @@
 #![allow(dead_code)]
+use crate::{
+    config::IntakeSettings,
+    store::{Message, MessageQueue, StoreFuture},
+};
 use aws_config::SdkConfig;
-use aws_sdk_sqs::{config::Builder, types::QueueAttributeName, Client};
+use aws_sdk_sqs::{
+    config::Builder,
+    types::{self, QueueAttributeName},
+    Client,
+};
 use chrono::Utc;
 use eyre::{Error, Result};
-use log::debug;
+use log::{debug, warn};
+use std::{sync::Arc, time::Duration};
 
 const MAX_QUEUE_HOURS: i64 = 2;
 const MAX_QUEUE_AGE: i64 = MAX_QUEUE_HOURS * 60 * 60;
@@ -84,16 +93,36 @@ async fn delete_message(client: &Client, url: &str, receipt_handle: &str) -> Res
     Ok(())
 }
 
-async fn receive_and_delete_messages(client: &Client, url: &String) -> Result<(), Error> {
-    let message = client
+/// Long-polls the queue for up to `wait_time` seconds, returning at most `batch_size` messages that
+/// stay hidden from other consumers for `visibility_timeout` seconds, or the queue's default.
+async fn receive_messages(
+    client: &Client,
+    url: &str,
+    wait_time: i32,
+    batch_size: i32,
+    visibility_timeout: Option<i32>,
+) -> Result<Vec<types::Message>, Error> {
+    let messages = client
         .receive_message()
         .queue_url(url)
+        .wait_time_seconds(wait_time)
+        .max_number_of_messages(batch_size)
+        .set_visibility_timeout(visibility_timeout)
         .send()
         .await?
         .messages
         .unwrap_or_default();
 
-    for message in message {
+    Ok(messages)
+}
+
+async fn receive_and_delete_messages(
+    client: &Client,
+    url: &str,
+    wait_time: i32,
+    batch_size: i32,
+) -> Result<(), Error> {
+    for message in receive_messages(client, url, wait_time, batch_size, None).await? {
         if let Some(receipt_handle) = message.receipt_handle() {
             debug!("Processing message: {:?}", message);
 
@@ -104,6 +133,103 @@ async fn receive_and_delete_messages(client: &Client, url: &String) -> Result<()
     Ok(())
 }
 
+async fn change_visibility(
+    client: &Client,
+    url: &str,
+    receipt_handle: &str,
+    visibility_timeout: i32,
+) -> Result<(), Error> {
+    client
+        .change_message_visibility()
+        .queue_url(url)
+        .receipt_handle(receipt_handle)
+        .visibility_timeout(visibility_timeout)
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// The URL of the queue named `queue`, or `queue` itself if it already is a URL.
+pub async fn queue_url(client: &Client, queue: &str) -> Result<String> {
+    if queue.starts_with("http://") || queue.starts_with("https://") {
+        return Ok(queue.to_string());
+    }
+    let response = client.get_queue_url().queue_name(queue).send().await?;
+    response
+        .queue_url()
+        .map(str::to_string)
+        .ok_or_else(|| eyre::eyre!("No URL returned for queue {}", queue))
+}
+
+/// The SQS queue that intake mode receives requests from.
+pub struct SqsQueue {
+    client: Arc<Client>,
+    url: String,
+    wait_time: i32,
+    batch_size: i32,
+    visibility_timeout: i32,
+}
+
+impl SqsQueue {
+    pub fn new(client: Arc<Client>, url: &str, settings: &IntakeSettings) -> Self {
+        let seconds = |value: u64| i32::try_from(value).unwrap_or(i32::MAX);
+        SqsQueue {
+            client,
+            url: url.to_string(),
+            wait_time: seconds(settings.wait_time),
+            batch_size: seconds(settings.batch_size.into()),
+            visibility_timeout: seconds(settings.visibility_timeout),
+        }
+    }
+}
+
+impl MessageQueue for SqsQueue {
+    fn receive(&self) -> StoreFuture<'_, Vec<Message>> {
+        Box::pin(async move {
+            let messages = receive_messages(
+                &self.client,
+                &self.url,
+                self.wait_time,
+                self.batch_size,
+                Some(self.visibility_timeout),
+            )
+            .await?;
+            Ok(messages
+                .into_iter()
+                .filter_map(|message| {
+                    let id = message.message_id.unwrap_or_default();
+                    let Some(receipt) = message.receipt_handle else {
+                        warn!("Message {} was received without a receipt handle", id);
+                        return None;
+                    };
+                    Some(Message {
+                        id,
+                        body: message.body.unwrap_or_default(),
+                        receipt,
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn delete<'a>(&'a self, message: &'a Message) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            delete_message(&self.client, &self.url, &message.receipt).await?;
+            debug!("Deleted message {} from {}", message.id, self.url);
+            Ok(())
+        })
+    }
+
+    fn extend_lease<'a>(&'a self, message: &'a Message, lease: Duration) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let lease = i32::try_from(lease.as_secs()).unwrap_or(i32::MAX);
+            change_visibility(&self.client, &self.url, &message.receipt, lease).await?;
+            Ok(())
+        })
+    }
+}
+
 pub async fn get_message(client: &Client, url: &String) -> Result<()> {
     Ok(receive(&client, url).await?)
 }
@@ -1,17 +1,837 @@
-use aws_config::{defaults, BehaviorVersion, SdkConfig};
-use eyre::Result;
-use utils::use_localstack;
-
-use crate::utils;
+use crate::{
+    analysis::analysis_jobs::Script,
+    models::{job_queue::QueueLimits, job_type::JobType},
+};
+use aws_config::{
+    defaults, sts::AssumeRoleProvider, BehaviorVersion, ConfigLoader, Region, SdkConfig,
+};
+use clap::Args;
+use eyre::{Result, WrapErr};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 const LOCALSTACK_ENDPOINT: &str = "http://localhost:4566/";
+const DEFAULT_CONFIG_FILE: &str = "visiproc.toml";
+
+/// Settings for one visiproc deployment. Every value comes from, in increasing precedence,
+/// the defaults below, a TOML file, `VISIPROC_*` environment variables and CLI flags.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub aws: AwsSettings,
+    pub tables: Tables,
+    pub buckets: Buckets,
+    pub topics: Topics,
+    pub scripts: Scripts,
+    pub queue: QueueSettings,
+    pub intake: IntakeSettings,
+}
+
+/// How to reach AWS. The top level values apply to every service unless the service's own
+/// table, e.g. `[aws.s3]`, overrides them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwsSettings {
+    pub localstack: bool,             // talk to a local localstack instead of AWS
+    pub endpoint_url: Option<String>, // overrides the endpoint of every service
+    pub region: Option<String>,       // defaults to the region of the environment or profile
+    pub profile: Option<String>,      // named profile from ~/.aws/config
+    pub role_arn: Option<String>,     // role to assume with the loaded credentials
+    pub external_id: Option<String>,  // required by some cross account roles
+    pub dynamodb: ServiceSettings,
+    pub sns: ServiceSettings,
+    pub sqs: ServiceSettings,
+    pub s3: ServiceSettings,
+    pub athena: ServiceSettings,
+}
+
+/// Overrides of the top level `[aws]` values for a single service.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceSettings {
+    pub endpoint_url: Option<String>,
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub role_arn: Option<String>,
+    pub external_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    DynamoDb,
+    Sns,
+    Sqs,
+    S3,
+    Athena,
+}
+
+impl Service {
+    pub const ALL: [Service; 5] = [
+        Service::DynamoDb,
+        Service::Sns,
+        Service::Sqs,
+        Service::S3,
+        Service::Athena,
+    ];
+
+    /// Name used in `VISIPROC_<NAME>_ENDPOINT_URL`.
+    fn env_name(self) -> &'static str {
+        match self {
+            Service::DynamoDb => "DYNAMODB",
+            Service::Sns => "SNS",
+            Service::Sqs => "SQS",
+            Service::S3 => "S3",
+            Service::Athena => "ATHENA",
+        }
+    }
+}
+
+impl AwsSettings {
+    fn overrides(&self, service: Service) -> &ServiceSettings {
+        match service {
+            Service::DynamoDb => &self.dynamodb,
+            Service::Sns => &self.sns,
+            Service::Sqs => &self.sqs,
+            Service::S3 => &self.s3,
+            Service::Athena => &self.athena,
+        }
+    }
+
+    fn overrides_mut(&mut self, service: Service) -> &mut ServiceSettings {
+        match service {
+            Service::DynamoDb => &mut self.dynamodb,
+            Service::Sns => &mut self.sns,
+            Service::Sqs => &mut self.sqs,
+            Service::S3 => &mut self.s3,
+            Service::Athena => &mut self.athena,
+        }
+    }
+
+    /// The settings `service` is reached with, its own overrides applied on top of the top level
+    /// values. Localstack's endpoint is used when no endpoint is configured at all.
+    pub fn service(&self, service: Service) -> ServiceSettings {
+        let overrides = self.overrides(service);
+        let pick = |own: &Option<String>, shared: &Option<String>| own.clone().or(shared.clone());
+        let endpoint_url = pick(&overrides.endpoint_url, &self.endpoint_url)
+            .or_else(|| self.localstack.then(|| LOCALSTACK_ENDPOINT.to_string()));
+        ServiceSettings {
+            endpoint_url,
+            region: pick(&overrides.region, &self.region),
+            profile: pick(&overrides.profile, &self.profile),
+            role_arn: pick(&overrides.role_arn, &self.role_arn),
+            external_id: pick(&overrides.external_id, &self.external_id),
+        }
+    }
+
+    /// Custom S3 endpoints, like localstack or MinIO, address buckets by path rather than by
+    /// subdomain.
+    pub fn s3_force_path_style(&self) -> bool {
+        self.service(Service::S3).endpoint_url.is_some()
+    }
+}
+
+impl ServiceSettings {
+    fn loader(&self) -> ConfigLoader {
+        let mut loader = defaults(BehaviorVersion::latest());
+        if let Some(endpoint_url) = &self.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+        if let Some(region) = &self.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(profile) = &self.profile {
+            loader = loader.profile_name(profile);
+        }
+        loader
+    }
+
+    /// Loads the AWS configuration for these settings, assuming `role_arn` if one is set.
+    pub async fn load(&self) -> SdkConfig {
+        let conf = self.loader().load().await;
+        let Some(role_arn) = &self.role_arn else {
+            return conf;
+        };
+
+        let mut provider = AssumeRoleProvider::builder(role_arn)
+            .session_name("visiproc")
+            .configure(&conf);
+        if let Some(external_id) = &self.external_id {
+            provider = provider.external_id(external_id);
+        }
+        self.loader()
+            .credentials_provider(provider.build().await)
+            .load()
+            .await
+    }
+}
+
+/// The AWS configuration of each service client.
+#[derive(Debug, Clone)]
+pub struct SdkConfigs {
+    pub dynamodb: SdkConfig,
+    pub sns: SdkConfig,
+    pub sqs: SdkConfig,
+    pub s3: SdkConfig,
+    pub athena: SdkConfig,
+}
+
+impl SdkConfigs {
+    pub async fn load(aws: &AwsSettings) -> Self {
+        let [dynamodb, sns, sqs, s3, athena] = Service::ALL.map(|service| aws.service(service));
+        let (dynamodb, sns, sqs, s3, athena) = tokio::join!(
+            dynamodb.load(),
+            sns.load(),
+            sqs.load(),
+            s3.load(),
+            athena.load(),
+        );
+        SdkConfigs {
+            dynamodb,
+            sns,
+            sqs,
+            s3,
+            athena,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tables {
+    pub requests: String,              // requests submitted by the frontend
+    pub requests_status_index: String, // GSI of `requests` keyed by jobStatus and creationDate
+    pub jobs: String,                  // job records, used to recover unfinished jobs
+    pub jobs_request_index: String,    // GSI of `jobs` keyed by requestID
+    pub jobs_worker_index: String,     // GSI of `jobs` keyed by workerID and jobStatus
+    pub responses: String,             // a response per finished job
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Tables {
+            requests: "mockRequests".to_string(),
+            requests_status_index: "StatusIndex".to_string(),
+            jobs: "Jobs".to_string(),
+            jobs_request_index: "RequestIDIndex".to_string(),
+            jobs_worker_index: "WorkerIDIndex".to_string(),
+            responses: "JobResponses".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Buckets {
+    pub results: String,       // job inputs and results, under a prefix per request
+    pub athena_output: String, // athena query results
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Buckets {
+            results: "metadata".to_string(),
+            athena_output: "aws-athena-query-results-000000000000-us-east-1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topics {
+    pub names: Vec<String>, // topics request updates are published to, all of them when empty
+}
+
+impl Topics {
+    /// Keeps the topic arns whose names are configured, or all of them if none are.
+    pub fn select(&self, topic_arns: Vec<String>) -> Vec<String> {
+        if self.names.is_empty() {
+            return topic_arns;
+        }
+        topic_arns
+            .into_iter()
+            .filter(|arn| {
+                let name = arn.rsplit(':').next().unwrap_or(arn);
+                self.names.iter().any(|configured| configured == name)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scripts {
+    pub interpreter: String, // runs the analysis scripts
+    pub dir: PathBuf,        // relative script paths are resolved against this
+    pub eda: PathBuf,
+    pub correlation: PathBuf,
+    pub simulated: PathBuf,
+    pub simulated_error: PathBuf,
+    pub eda_config: PathBuf, // ydata-profiling settings of the EDA script
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts {
+            interpreter: "python".to_string(),
+            dir: PathBuf::from("python_jobs"),
+            eda: PathBuf::from("eda_analysis.py"),
+            correlation: PathBuf::from("correlation_analysis.py"),
+            simulated: PathBuf::from("simulated_analysis.py"),
+            simulated_error: PathBuf::from("simulated_error.py"),
+            eda_config: PathBuf::from("ydata-config.yaml"),
+        }
+    }
+}
+
+impl Scripts {
+    pub fn script(&self, job_type: JobType) -> Script {
+        let path = match job_type {
+            JobType::Eda => &self.eda,
+            JobType::Corr => &self.correlation,
+            JobType::SimulatedJob => &self.simulated,
+            JobType::SimulatedError => &self.simulated_error,
+            JobType::None => Path::new(""),
+        };
+        let mut script = Script {
+            interpreter: self.interpreter.clone(),
+            path: self.dir.join(path),
+            env: Vec::new(),
+        };
+        if job_type == JobType::Eda {
+            let eda_config = self.dir.join(&self.eda_config);
+            script.env.push((
+                "VISIPROC_EDA_CONFIG".to_string(),
+                eda_config.display().to_string(),
+            ));
+        }
+        script
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueSettings {
+    pub interval: u64,          // seconds between polling cycles in serve mode
+    pub workers: Option<usize>, // jobs running at once, across all types
+    #[serde(deserialize_with = "job_type_map")]
+    pub type_limits: HashMap<JobType, usize>, // jobs running at once, per type
+    #[serde(deserialize_with = "job_type_map")]
+    pub max_attempts: HashMap<JobType, u32>, // runs per job, per type
+    #[serde(deserialize_with = "job_type_map")]
+    pub timeouts: HashMap<JobType, u64>, // seconds a single run may take, per type
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        QueueSettings {
+            interval: 30,
+            workers: None,
+            type_limits: HashMap::new(),
+            max_attempts: HashMap::new(),
+            timeouts: HashMap::new(),
+        }
+    }
+}
+
+impl QueueSettings {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    /// The default limits with the configured ones applied on top.
+    pub fn limits(&self) -> QueueLimits {
+        let mut limits = QueueLimits::default();
+        if let Some(workers) = self.workers {
+            limits.max_workers = workers;
+        }
+        limits.type_limits.extend(self.type_limits.clone());
+        limits
+    }
+}
+
+/// How intake mode receives requests from SQS.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntakeSettings {
+    pub queue: String,           // name or URL of the queue requests are sent to
+    pub wait_time: u64,          // seconds a receive long-polls for messages, at most 20
+    pub batch_size: u32,         // messages received at once, at most 10
+    pub visibility_timeout: u64, // seconds a received message stays hidden, renewed while it runs
+}
+
+impl Default for IntakeSettings {
+    fn default() -> Self {
+        IntakeSettings {
+            queue: "requestQueue".to_string(),
+            wait_time: 20,
+            batch_size: 10,
+            visibility_timeout: 300,
+        }
+    }
+}
+
+impl IntakeSettings {
+    pub fn visibility_timeout(&self) -> Duration {
+        Duration::from_secs(self.visibility_timeout)
+    }
+}
+
+/// Reads a table keyed by analysis type names, e.g. `"Exploratory Data Analysis" = 2`.
+fn job_type_map<'de, D, T>(deserializer: D) -> Result<HashMap<JobType, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    HashMap::<String, T>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(job_type, value)| {
+            let job_type = job_type
+                .parse::<JobType>()
+                .map_err(|_| de::Error::custom(format!("unknown analysis type `{job_type}`")))?;
+            Ok((job_type, value))
+        })
+        .collect()
+}
+
+/// CLI flags that override the configuration file and environment.
+#[derive(Debug, Default, Args)]
+pub struct ConfigArgs {
+    /// Configuration file, defaults to ./visiproc.toml if it exists
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Use a local localstack instead of AWS
+    #[arg(long, global = true)]
+    pub localstack: bool,
+    /// AWS endpoint for every service, e.g. a localstack on another host
+    #[arg(long, global = true)]
+    pub endpoint_url: Option<String>,
+    /// AWS region
+    #[arg(long, global = true)]
+    pub region: Option<String>,
+    /// AWS profile
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Role to assume for every service
+    #[arg(long, global = true)]
+    pub role_arn: Option<String>,
+    /// Requests table
+    #[arg(long, global = true)]
+    pub requests_table: Option<String>,
+    /// Jobs table
+    #[arg(long, global = true)]
+    pub jobs_table: Option<String>,
+    /// Job responses table
+    #[arg(long, global = true)]
+    pub responses_table: Option<String>,
+    /// Bucket holding job inputs and results
+    #[arg(long, global = true)]
+    pub results_bucket: Option<String>,
+    /// Topic to publish request updates to, repeat for several
+    #[arg(long = "topic", global = true)]
+    pub topics: Vec<String>,
+    /// Directory holding the analysis scripts
+    #[arg(long, global = true)]
+    pub scripts_dir: Option<PathBuf>,
+    /// Name or URL of the SQS queue intake mode receives requests from
+    #[arg(long, global = true)]
+    pub intake_queue: Option<String>,
+}
+
+/// Serve mode flags that override the `[queue]` settings.
+#[derive(Debug, Default, Args)]
+pub struct QueueArgs {
+    /// Seconds to wait between polling cycles
+    #[arg(long)]
+    pub interval: Option<u64>,
+    /// Maximum number of jobs running at once
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// Per analysis type limit, e.g. "Exploratory Data Analysis=2"
+    #[arg(long, value_parser = parse_type_value::<usize>)]
+    pub type_limit: Vec<(JobType, usize)>,
+    /// Per analysis type retry attempts, e.g. "Correlation=5"
+    #[arg(long, value_parser = parse_type_value::<u32>)]
+    pub max_attempts: Vec<(JobType, u32)>,
+    /// Per analysis type timeout in seconds, e.g. "Correlation=600"
+    #[arg(long, value_parser = parse_type_value::<u64>)]
+    pub timeout: Vec<(JobType, u64)>,
+}
+
+fn parse_type_value<T: FromStr>(s: &str) -> Result<(JobType, T), String>
+where
+    T::Err: fmt::Display,
+{
+    let (job_type, value) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected <analysis type>=<value>, got `{s}`"))?;
+    let job_type = job_type
+        .trim()
+        .parse::<JobType>()
+        .map_err(|e| e.to_string())?;
+    let value = value.trim().parse::<T>().map_err(|e| e.to_string())?;
+    Ok((job_type, value))
+}
+
+impl Config {
+    /// Reads `path`, or `./visiproc.toml` if no path is given and it exists.
+    pub fn from_file(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Applies the `VISIPROC_*` (and `LOCALSTACK`) variables found by `var`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        fn parse<T: FromStr>(name: &str, value: String) -> Result<T>
+        where
+            T::Err: fmt::Display,
+        {
+            value
+                .parse()
+                .map_err(|e| eyre::eyre!("Invalid {}: {}", name, e))
+        }
+
+        if let Some(value) = var("LOCALSTACK") {
+            self.aws.localstack = value == "true";
+        }
+        if let Some(value) = var("VISIPROC_ENDPOINT_URL") {
+            self.aws.endpoint_url = Some(value);
+        }
+        for service in Service::ALL {
+            if let Some(value) = var(&format!("VISIPROC_{}_ENDPOINT_URL", service.env_name())) {
+                self.aws.overrides_mut(service).endpoint_url = Some(value);
+            }
+        }
+        if let Some(value) = var("VISIPROC_AWS_REGION") {
+            self.aws.region = Some(value);
+        }
+        if let Some(value) = var("VISIPROC_AWS_PROFILE") {
+            self.aws.profile = Some(value);
+        }
+        if let Some(value) = var("VISIPROC_ROLE_ARN") {
+            self.aws.role_arn = Some(value);
+        }
+        if let Some(value) = var("VISIPROC_EXTERNAL_ID") {
+            self.aws.external_id = Some(value);
+        }
+        if let Some(value) = var("VISIPROC_REQUESTS_TABLE") {
+            self.tables.requests = value;
+        }
+        if let Some(value) = var("VISIPROC_REQUESTS_STATUS_INDEX") {
+            self.tables.requests_status_index = value;
+        }
+        if let Some(value) = var("VISIPROC_JOBS_TABLE") {
+            self.tables.jobs = value;
+        }
+        if let Some(value) = var("VISIPROC_JOBS_REQUEST_INDEX") {
+            self.tables.jobs_request_index = value;
+        }
+        if let Some(value) = var("VISIPROC_JOBS_WORKER_INDEX") {
+            self.tables.jobs_worker_index = value;
+        }
+        if let Some(value) = var("VISIPROC_RESPONSES_TABLE") {
+            self.tables.responses = value;
+        }
+        if let Some(value) = var("VISIPROC_RESULTS_BUCKET") {
+            self.buckets.results = value;
+        }
+        if let Some(value) = var("VISIPROC_ATHENA_OUTPUT_BUCKET") {
+            self.buckets.athena_output = value;
+        }
+        if let Some(value) = var("VISIPROC_TOPICS") {
+            self.topics.names = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(value) = var("VISIPROC_PYTHON") {
+            self.scripts.interpreter = value;
+        }
+        if let Some(value) = var("VISIPROC_SCRIPTS_DIR") {
+            self.scripts.dir = PathBuf::from(value);
+        }
+        if let Some(value) = var("VISIPROC_POLL_INTERVAL") {
+            self.queue.interval = parse("VISIPROC_POLL_INTERVAL", value)?;
+        }
+        if let Some(value) = var("VISIPROC_WORKERS") {
+            self.queue.workers = Some(parse("VISIPROC_WORKERS", value)?);
+        }
+        if let Some(value) = var("VISIPROC_INTAKE_QUEUE") {
+            self.intake.queue = value;
+        }
+        Ok(())
+    }
+
+    pub fn apply_args(&mut self, args: &ConfigArgs) {
+        if args.localstack {
+            self.aws.localstack = true;
+        }
+        if let Some(endpoint_url) = &args.endpoint_url {
+            self.aws.endpoint_url = Some(endpoint_url.clone());
+        }
+        if let Some(region) = &args.region {
+            self.aws.region = Some(region.clone());
+        }
+        if let Some(profile) = &args.profile {
+            self.aws.profile = Some(profile.clone());
+        }
+        if let Some(role_arn) = &args.role_arn {
+            self.aws.role_arn = Some(role_arn.clone());
+        }
+        if let Some(table) = &args.requests_table {
+            self.tables.requests = table.clone();
+        }
+        if let Some(table) = &args.jobs_table {
+            self.tables.jobs = table.clone();
+        }
+        if let Some(table) = &args.responses_table {
+            self.tables.responses = table.clone();
+        }
+        if let Some(bucket) = &args.results_bucket {
+            self.buckets.results = bucket.clone();
+        }
+        if !args.topics.is_empty() {
+            self.topics.names = args.topics.clone();
+        }
+        if let Some(dir) = &args.scripts_dir {
+            self.scripts.dir = dir.clone();
+        }
+        if let Some(queue) = &args.intake_queue {
+            self.intake.queue = queue.clone();
+        }
+    }
+
+    /// How to run the script of `job_type`. Scripts that read their input from S3 themselves reach
+    /// it the way visiproc does, through `AWS_ENDPOINT_URL_S3`, `AWS_REGION` and `AWS_PROFILE`.
+    pub fn script(&self, job_type: JobType) -> Script {
+        let mut script = self.scripts.script(job_type);
+        let s3 = self.aws.service(Service::S3);
+        let aws_env = [
+            ("AWS_ENDPOINT_URL_S3", s3.endpoint_url),
+            ("AWS_REGION", s3.region),
+            ("AWS_PROFILE", s3.profile),
+        ];
+        script.env.extend(
+            aws_env
+                .into_iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value?))),
+        );
+        script
+    }
+
+    pub fn apply_queue_args(&mut self, args: &QueueArgs) {
+        if let Some(interval) = args.interval {
+            self.queue.interval = interval;
+        }
+        if let Some(workers) = args.workers {
+            self.queue.workers = Some(workers);
+        }
+        self.queue
+            .type_limits
+            .extend(args.type_limit.iter().cloned());
+        self.queue
+            .max_attempts
+            .extend(args.max_attempts.iter().cloned());
+        self.queue.timeouts.extend(args.timeout.iter().cloned());
+    }
+}
+
+/// Loads the layered configuration, then the AWS configuration of each service it points at.
+pub async fn configure(
+    args: &ConfigArgs,
+    queue_args: Option<&QueueArgs>,
+) -> Result<(Config, SdkConfigs)> {
+    let config_file = args
+        .config
+        .clone()
+        .or_else(|| std::env::var("VISIPROC_CONFIG").ok().map(PathBuf::from));
+    let mut config = Config::from_file(config_file.as_deref())?;
+    config.apply_env(|name| std::env::var(name).ok())?;
+    config.apply_args(args);
+    if let Some(queue_args) = queue_args {
+        config.apply_queue_args(queue_args);
+    }
+
+    let sdk_configs = SdkConfigs::load(&config.aws).await;
+    Ok((config, sdk_configs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_values_override_the_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [tables]
+            requests = "stagingRequests"
+
+            [queue]
+            workers = 8
+            type_limits = { "Exploratory Data Analysis" = 1 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.tables.requests, "stagingRequests");
+        assert_eq!(config.tables.jobs, "Jobs");
+        assert_eq!(config.buckets, Buckets::default());
+
+        let limits = config.queue.limits();
+        assert_eq!(limits.max_workers, 8);
+        assert_eq!(limits.type_limits, HashMap::from([(JobType::Eda, 1)]));
+    }
+
+    #[test]
+    fn example_file_matches_the_defaults() {
+        let config: Config = toml::from_str(include_str!("visiproc.example.toml")).unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.tables, defaults.tables);
+        assert_eq!(config.buckets, defaults.buckets);
+        assert_eq!(config.scripts, defaults.scripts);
+        assert_eq!(config.intake, defaults.intake);
+        assert_eq!(
+            config.queue.limits().type_limits,
+            defaults.queue.limits().type_limits
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_analysis_types_are_rejected() {
+        assert!(toml::from_str::<Config>("[tables]\nrequest = \"typo\"").is_err());
+        assert!(toml::from_str::<Config>("[queue]\ntimeouts = { Eda = 10 }").is_err());
+    }
+
+    #[test]
+    fn cli_flags_override_env_which_overrides_the_file() {
+        let mut config: Config =
+            toml::from_str("[tables]\nrequests = \"fileRequests\"\njobs = \"fileJobs\"").unwrap();
+
+        let env = HashMap::from([
+            ("VISIPROC_REQUESTS_TABLE", "envRequests"),
+            ("VISIPROC_JOBS_TABLE", "envJobs"),
+            ("VISIPROC_TOPICS", "updates, audit"),
+        ]);
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+        config.apply_args(&ConfigArgs {
+            requests_table: Some("cliRequests".to_string()),
+            ..ConfigArgs::default()
+        });
+
+        assert_eq!(config.tables.requests, "cliRequests");
+        assert_eq!(config.tables.jobs, "envJobs");
+        assert_eq!(config.topics.names, vec!["updates", "audit"]);
+    }
+
+    #[test]
+    fn invalid_env_values_are_reported() {
+        let err = Config::default()
+            .apply_env(|name| (name == "VISIPROC_WORKERS").then(|| "many".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("VISIPROC_WORKERS"));
+    }
+
+    #[test]
+    fn service_settings_fall_back_to_the_top_level() {
+        let config: Config = toml::from_str(
+            r#"
+            [aws]
+            region = "eu-west-1"
+            role_arn = "arn:aws:iam::000000000000:role/visiproc"
+
+            [aws.s3]
+            endpoint_url = "http://minio:9000"
+            region = "us-east-1"
+            "#,
+        )
+        .unwrap();
+
+        let s3 = config.aws.service(Service::S3);
+        assert_eq!(s3.endpoint_url.as_deref(), Some("http://minio:9000"));
+        assert_eq!(s3.region.as_deref(), Some("us-east-1"));
+        assert_eq!(s3.role_arn, config.aws.role_arn);
+        assert!(config.aws.s3_force_path_style());
+
+        let sqs = config.aws.service(Service::Sqs);
+        assert_eq!(sqs.endpoint_url, None);
+        assert_eq!(sqs.region.as_deref(), Some("eu-west-1"));
+    }
+
+    #[test]
+    fn scripts_reach_s3_like_visiproc() {
+        let config: Config = toml::from_str(
+            r#"
+            [aws]
+            localstack = true
+            region = "us-east-1"
+            "#,
+        )
+        .unwrap();
+
+        let env = |job_type| config.script(job_type).env;
+        let eda = env(JobType::Eda);
+        let var = |name: &str| {
+            eda.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("AWS_ENDPOINT_URL_S3"), Some(LOCALSTACK_ENDPOINT));
+        assert_eq!(var("AWS_REGION"), Some("us-east-1"));
+        assert_eq!(var("AWS_PROFILE"), None);
+        assert_eq!(
+            var("VISIPROC_EDA_CONFIG"),
+            Some("python_jobs/ydata-config.yaml")
+        );
+        assert_eq!(env(JobType::SimulatedJob).len(), 2);
+    }
+
+    #[test]
+    fn localstack_is_the_fallback_endpoint() {
+        let mut config = Config::default();
+        config
+            .apply_env(|name| match name {
+                "LOCALSTACK" => Some("true".to_string()),
+                "VISIPROC_SNS_ENDPOINT_URL" => Some("http://localstack:4566/".to_string()),
+                _ => None,
+            })
+            .unwrap();
+
+        let endpoint = |service| config.aws.service(service).endpoint_url;
+        assert_eq!(
+            endpoint(Service::Sns).as_deref(),
+            Some("http://localstack:4566/")
+        );
+        assert_eq!(endpoint(Service::Sqs).as_deref(), Some(LOCALSTACK_ENDPOINT));
+    }
 
-pub async fn configure() -> Result<SdkConfig> {
-    let mut shared_config = defaults(BehaviorVersion::latest());
-    if use_localstack() {
-        shared_config = shared_config.endpoint_url(LOCALSTACK_ENDPOINT);
-    };
-    let shared_config = shared_config.load().await;
+    #[test]
+    fn topics_select_configured_names() {
+        let arns = vec![
+            "arn:aws:sns:us-east-1:000000000000:updates".to_string(),
+            "arn:aws:sns:us-east-1:000000000000:other".to_string(),
+        ];
+        assert_eq!(Topics::default().select(arns.clone()), arns);
 
-    Ok(shared_config)
+        let topics = Topics {
+            names: vec!["updates".to_string()],
+        };
+        assert_eq!(topics.select(arns.clone()), vec![arns[0].clone()]);
+    }
 }
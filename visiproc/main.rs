@@ -2,34 +2,133 @@ mod analysis;
 mod aws;
 mod config;
 mod models;
+mod store;
 mod tasks;
 mod utils;
 
 pub(crate) use crate::{
     aws::{
-        dynamodb::dynamodb_client,
-        sns::{list_topics, sns_client},
-        sqs::{delete_old_queues, get_message, list_queues, sqs_client},
+        athena::athena_client,
+        dynamodb::{dynamodb_client, DynamoDbJobs, DynamoDbRequests},
+        s3::S3Blobs,
+        sns::{sns_client, SnsPublisher},
+        sqs::{delete_old_queues, get_message, list_queues, queue_url, sqs_client, SqsQueue},
     },
-    tasks::queue::queue_new_requests,
+    tasks::{intake::intake, queue::queue_new_requests, recovery::recover_jobs, serve::serve},
     utils::init_logging,
 };
+use analysis::retry::RetryPolicy;
 use aws::s3::{list_buckets, list_objects, s3_client};
 
 use clap::{Parser, Subcommand};
+use config::{Config, ConfigArgs, QueueArgs};
 use eyre::Result;
-use models::job_queue::JobQueue;
+use models::job_queue::{CancelHandle, JobQueue};
 use std::{
     io::{self, Write},
     sync::Arc,
+    time::Duration,
 };
-use tasks::queue::publish_complete_requests;
+use store::{BlobStore, EventPublisher, JobStore, RequestStore};
+use tasks::{cancel::watch_cancellations, queue::publish_complete_requests};
+use tokio::sync::Mutex;
 
+#[derive(Clone)]
 struct Clients {
     dynamodb: Arc<aws_sdk_dynamodb::Client>,
     sns: Arc<aws_sdk_sns::Client>,
     sqs: Arc<aws_sdk_sqs::Client>,
     s3: Arc<aws_sdk_s3::Client>,
+    #[allow(dead_code)] // no REPL command queries athena yet
+    athena: Arc<aws_sdk_athena::Client>,
+}
+
+/// The stores backed by `Clients`.
+#[derive(Clone)]
+struct Stores {
+    requests: Arc<dyn RequestStore>,
+    jobs: Arc<dyn JobStore>,
+    publisher: Arc<dyn EventPublisher>,
+    blobs: Arc<dyn BlobStore>,
+}
+
+impl Stores {
+    fn new(config: &Config, clients: &Clients) -> Self {
+        let tables = &config.tables;
+        Stores {
+            requests: Arc::new(DynamoDbRequests::new(
+                clients.dynamodb.clone(),
+                &tables.requests,
+                &tables.requests_status_index,
+            )),
+            jobs: Arc::new(DynamoDbJobs::new(
+                clients.dynamodb.clone(),
+                &tables.jobs,
+                &tables.jobs_request_index,
+                &tables.jobs_worker_index,
+                &tables.responses,
+            )),
+            publisher: Arc::new(SnsPublisher::new(clients.sns.clone())),
+            blobs: Arc::new(S3Blobs::new(clients.s3.clone())),
+        }
+    }
+}
+
+/// State kept across REPL commands.
+struct Session {
+    config: Arc<Config>,
+    clients: Clients,
+    stores: Stores,
+    job_queue: Arc<Mutex<JobQueue>>, // locked while its jobs run in the background
+    cancel: CancelHandle,            // cancels jobs without waiting for the lock
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Mode {
+    /// Continuously poll for PENDING requests until SIGINT/SIGTERM
+    Serve {
+        #[command(flatten)]
+        queue: QueueArgs,
+    },
+    /// Continuously receive requests from the SQS intake queue until SIGINT/SIGTERM
+    Intake {
+        #[command(flatten)]
+        queue: QueueArgs,
+    },
+}
+
+/// A queue set up with the `[queue]` settings of `config`.
+fn new_job_queue(config: &Config, stores: &Stores) -> JobQueue {
+    let settings = &config.queue;
+    let job_queue = JobQueue::new(stores.blobs.clone())
+        .with_limits(settings.limits())
+        .with_job_store(stores.jobs.clone());
+    let job_queue =
+        settings
+            .max_attempts
+            .iter()
+            .fold(job_queue, |job_queue, (job_type, max_attempts)| {
+                let policy = RetryPolicy {
+                    max_attempts: *max_attempts,
+                    ..RetryPolicy::for_job_type(*job_type)
+                };
+                job_queue.with_retry_policy(*job_type, policy)
+            });
+    settings
+        .timeouts
+        .iter()
+        .fold(job_queue, |job_queue, (job_type, secs)| {
+            job_queue.with_timeout(*job_type, Duration::from_secs(*secs))
+        })
 }
 
 #[derive(Debug, Parser)]
@@ -51,8 +150,10 @@ enum Commands {
     ListMessages,
     /// Queue jobs
     QueueJobs,
-    /// Process queued jobs
+    /// Process queued jobs in the background
     ProcessQueuedJobs,
+    /// Cancel a queued or running job
+    CancelJob { job_id: String },
     /// Publish completed jobs
     CompleteJobs,
     /// Injest parquet and display it
@@ -63,15 +164,52 @@ enum Commands {
     Exit,
 }
 
-async fn respond(line: &str, clients: &Clients) -> Result<bool, eyre::Report> {
+/// Runs the queued jobs until they finish, stopping those of requests moved to CANCEL_REQUESTED.
+async fn run_watching_cancellations(
+    job_queue: &JobQueue,
+    config: &Config,
+    stores: &Stores,
+    topics: &[String],
+) -> Result<()> {
+    tokio::select! {
+        result = job_queue.run() => result,
+        () = watch_cancellations(
+            stores.requests.as_ref(),
+            stores.jobs.as_ref(),
+            stores.publisher.as_ref(),
+            topics,
+            job_queue,
+            config.queue.interval(),
+        ) => Ok(()),
+    }
+}
+
+/// Runs the queued jobs on a background task so they can be cancelled from the REPL,
+/// printing the queue once they finish.
+fn run_in_background(session: &Session, topics: Vec<String>) {
+    let (job_queue, config, stores) = (
+        session.job_queue.clone(),
+        session.config.clone(),
+        session.stores.clone(),
+    );
+    tokio::spawn(async move {
+        let job_queue = job_queue.lock().await;
+        if let Err(err) = run_watching_cancellations(&job_queue, &config, &stores, &topics).await {
+            println!("Processing queued jobs failed: {err:#}");
+        }
+        println!("{:#}", job_queue);
+    });
+}
+
+async fn respond(line: &str, session: &Session) -> Result<bool, eyre::Report> {
+    let (config, clients, stores) = (&session.config, &session.clients, &session.stores);
     let args = shlex::split(line).ok_or_else(|| eyre::eyre!("Invalid quoting"))?;
     let cli = Cli::try_parse_from(args)?;
 
     let queues = list_queues(&clients.sqs).await?;
 
     // not an AWS SQS, this is the queue for the actual python jobs running
-    let topics = list_topics(&clients.sns).await?;
-    let mut job_queue = JobQueue::new();
+    let topics = config.topics.select(stores.publisher.topics().await?);
 
     match cli.command {
         Commands::ListTopics => {
@@ -94,10 +232,28 @@ async fn respond(line: &str, clients: &Clients) -> Result<bool, eyre::Report> {
             }
         }
         Commands::QueueJobs => {
-            queue_new_requests(&clients.dynamodb, &clients.sns, &topics, &mut job_queue).await?;
+            let mut job_queue = session
+                .job_queue
+                .try_lock()
+                .map_err(|_| eyre::eyre!("Jobs are running, try again once they finish"))?;
+            queue_new_requests(
+                stores.requests.as_ref(),
+                stores.publisher.as_ref(),
+                config,
+                &topics,
+                &mut job_queue,
+            )
+            .await?;
         }
         Commands::ProcessQueuedJobs => {
-            job_queue.run().await?;
+            run_in_background(session, topics);
+        }
+        Commands::CancelJob { job_id } => {
+            if session.cancel.cancel(&job_id) {
+                println!("Cancelling job {}", job_id);
+            } else {
+                println!("No job {} in the queue", job_id);
+            }
         }
         Commands::CompleteJobs => {
             todo!()
@@ -119,29 +275,104 @@ async fn respond(line: &str, clients: &Clients) -> Result<bool, eyre::Report> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
     let _init_logging = init_logging()?;
-    let shared_config = config::configure().await?;
+    let queue_args = args.mode.as_ref().map(|mode| match mode {
+        Mode::Serve { queue } | Mode::Intake { queue } => queue,
+    });
+    let (config, sdk_configs) = config::configure(&args.config, queue_args).await?;
+    let config = Arc::new(config);
 
     let clients = Clients {
-        dynamodb: Arc::new(dynamodb_client(&shared_config)),
-        sns: Arc::new(sns_client(&shared_config)),
-        sqs: Arc::new(sqs_client(&shared_config)),
-        s3: Arc::new(s3_client(&shared_config)),
+        dynamodb: Arc::new(dynamodb_client(&sdk_configs.dynamodb)),
+        sns: Arc::new(sns_client(&sdk_configs.sns)),
+        sqs: Arc::new(sqs_client(&sdk_configs.sqs)),
+        s3: Arc::new(s3_client(&sdk_configs.s3, config.aws.s3_force_path_style())),
+        athena: Arc::new(athena_client(&sdk_configs.athena)),
     };
 
-    let topics = list_topics(&clients.sns).await?;
-    let mut job_queue = JobQueue::new();
-    println!("{:#}", job_queue);
+    let stores = Stores::new(&config, &clients);
 
-    queue_new_requests(&clients.dynamodb, &clients.sns, &topics, &mut job_queue).await?;
-    println!("{:#}", job_queue);
+    match args.mode {
+        Some(Mode::Serve { .. }) => {
+            return serve(
+                stores.requests.as_ref(),
+                stores.jobs.as_ref(),
+                stores.publisher.as_ref(),
+                &config,
+                || new_job_queue(&config, &stores),
+            )
+            .await;
+        }
+        Some(Mode::Intake { .. }) => {
+            let url = queue_url(&clients.sqs, &config.intake.queue).await?;
+            let messages = SqsQueue::new(clients.sqs.clone(), &url, &config.intake);
+            return intake(
+                stores.requests.as_ref(),
+                stores.jobs.as_ref(),
+                stores.publisher.as_ref(),
+                &messages,
+                &config,
+                || new_job_queue(&config, &stores),
+            )
+            .await;
+        }
+        None => {}
+    }
 
-    job_queue.run().await?;
+    let topics = config.topics.select(stores.publisher.topics().await?);
+    let mut job_queue = new_job_queue(&config, &stores);
+    recover_jobs(
+        stores.requests.as_ref(),
+        stores.jobs.as_ref(),
+        &config,
+        &mut job_queue,
+    )
+    .await?;
     println!("{:#}", job_queue);
 
-    publish_complete_requests(&clients.dynamodb, &clients.sns, &topics, &mut job_queue).await?;
+    queue_new_requests(
+        stores.requests.as_ref(),
+        stores.publisher.as_ref(),
+        &config,
+        &topics,
+        &mut job_queue,
+    )
+    .await?;
     println!("{:#}", job_queue);
 
+    let session = Session {
+        config: config.clone(),
+        cancel: job_queue.cancel_handle(),
+        job_queue: Arc::new(Mutex::new(job_queue)),
+        clients,
+        stores: stores.clone(),
+    };
+
+    // run and publish in the background so the jobs can be cancelled from the REPL
+    let mut job_queue = session.job_queue.clone().lock_owned().await;
+    tokio::spawn(async move {
+        let result = async {
+            run_watching_cancellations(&job_queue, &config, &stores, &topics).await?;
+            println!("{:#}", *job_queue);
+
+            publish_complete_requests(
+                stores.requests.as_ref(),
+                stores.jobs.as_ref(),
+                stores.publisher.as_ref(),
+                &topics,
+                &mut job_queue,
+            )
+            .await?;
+            println!("{:#}", *job_queue);
+            Ok::<(), eyre::Report>(())
+        }
+        .await;
+        if let Err(err) = result {
+            println!("Processing queued jobs failed: {err:#}");
+        }
+    });
+
     loop {
         print!("$ ");
         io::stdout().flush().unwrap();
@@ -149,7 +380,7 @@ async fn main() -> Result<()> {
         io::stdin().read_line(&mut command)?;
         let command = command.trim();
 
-        match respond(command, &clients).await {
+        match respond(command, &session).await {
             Ok(quit) => {
                 if quit {
                     break;
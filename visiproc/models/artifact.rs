@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// An uploaded result of a job, recorded on the job and its responses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub kind: String, // what the file holds, e.g. "report" or "data"
+    pub uri: String,  // s3 uri it was uploaded to
+    #[serde(rename = "mimeType")]
+    pub mime_type: String, // content type it was uploaded with
+    pub description: String, // human readable summary
+}
@@ -1,25 +1,148 @@
 #![allow(dead_code)]
+use crate::{aws::s3, models::job_request::JobRequest};
 use arrow::{
-    array::{BooleanArray, TimestampMillisecondArray},
-    compute::filter_record_batch,
+    array::{
+        make_array, Array, ArrayRef, AsArray, BooleanArray, Float64Array,
+        TimestampMillisecondArray, UInt64Array,
+    },
+    buffer::NullBuffer,
+    compute::{cast, cast_with_options, filter_record_batch, CastOptions},
     csv,
-    datatypes::{DataType, Schema, TimeUnit},
-    record_batch::RecordBatch,
+    datatypes::{DataType, Field, Float64Type, Schema, TimeUnit, TimestampMillisecondType},
+    record_batch::{RecordBatch, RecordBatchReader},
 };
 use arrow_csv::reader::Format;
+use aws_sdk_s3::Client;
+use bytes::{Buf, Bytes};
 use eyre::Result;
+use flate2::read::MultiGzDecoder;
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
 use log::debug;
 use parquet::{
-    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
-    basic::Compression,
-    file::properties::WriterProperties,
+    arrow::{
+        arrow_reader::{
+            ArrowPredicateFn, ArrowReaderBuilder, ArrowReaderOptions,
+            ParquetRecordBatchReaderBuilder, RowFilter, RowSelection, RowSelector,
+        },
+        async_reader::{AsyncFileReader, ParquetRecordBatchStreamBuilder},
+        ArrowWriter, ProjectionMask,
+    },
+    basic::{Compression, ConvertedType, LogicalType, TimeUnit as ParquetTimeUnit},
+    file::{
+        metadata::ParquetMetaData, page_index::index::Index, properties::WriterProperties,
+        statistics::Statistics,
+    },
+    schema::types::ColumnDescriptor,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Cursor, Read, Seek, Write},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
 };
-use std::{fs::File, io::Seek, sync::Arc};
+
+/// Rows per record batch read from CSV and NDJSON.
+const BATCH_SIZE: usize = 512;
+
+/// How much of a CSV or NDJSON object is read to infer its schema before decoding it.
+const SCHEMA_SAMPLE_BYTES: usize = 1024 * 1024;
+
+/// Schema metadata key naming the time column, e.g. in the key-value metadata of a Parquet file.
+pub const TIME_COLUMN_KEY: &str = "time_column";
+
+/// How `TimeSeriesData::resample` summarises a numeric column within each time bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Mean,
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregation {
+    pub const ALL: [Aggregation; 7] = [
+        Aggregation::Mean,
+        Aggregation::Min,
+        Aggregation::Max,
+        Aggregation::Sum,
+        Aggregation::Count,
+        Aggregation::First,
+        Aggregation::Last,
+    ];
+
+    /// Suffix of the resampled column, e.g. `temperature_mean`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Sum => "sum",
+            Aggregation::Count => "count",
+            Aggregation::First => "first",
+            Aggregation::Last => "last",
+        }
+    }
+}
+
+/// The non-null values of one column that fell into one time bucket.
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: Option<(i64, f64)>, // earliest value and its time
+    last: Option<(i64, f64)>,  // latest value and its time
+}
+
+impl Bucket {
+    fn add(&mut self, time: i64, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.first.is_none_or(|(first, _)| time < first) {
+            self.first = Some((time, value));
+        }
+        if self.last.is_none_or(|(last, _)| time >= last) {
+            self.last = Some((time, value));
+        }
+    }
+
+    /// The aggregated value, `None` when the bucket holds no values for the column.
+    fn value(&self, aggregation: Aggregation) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        match aggregation {
+            Aggregation::Mean => Some(self.sum / self.count as f64),
+            Aggregation::Min => Some(self.min),
+            Aggregation::Max => Some(self.max),
+            Aggregation::Sum => Some(self.sum),
+            Aggregation::Count => Some(self.count as f64),
+            Aggregation::First => self.first.map(|(_, value)| value),
+            Aggregation::Last => self.last.map(|(_, value)| value),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TimeSeriesData {
     schema: Arc<Schema>,
     record_batches: Vec<RecordBatch>,
+    time_column: Option<String>, // named by the caller, otherwise found by `time_column`
 }
 
 impl TimeSeriesData {
@@ -50,6 +173,51 @@ impl TimeSeriesData {
         Ok(TimeSeriesData {
             schema,
             record_batches,
+            time_column: None,
+        })
+    }
+
+    /// Constructs a `TimeSeriesData` instance from the parts of a Parquet file on disk that are needed,
+    /// rather than reading all of it and filtering afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `infile` - A string slice that holds the path to the input Parquet file.
+    /// * `columns` - The names of the columns to read, or `None` to read every column.
+    /// * `time_range` - The start and end, in milliseconds, of the rows to keep, or `None` to keep every row.
+    /// * `time_column` - The column `time_range` applies to, or `None` to find it like `time_column` does.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesData` instance with the selected columns of the rows in
+    /// `time_range`, as `filter_by_time_range` would keep them, and `time_column` as its time column if it was read,
+    /// or an error if a column is missing or the file has no time column. Row groups and pages whose time column statistics fall outside
+    /// the range are skipped without being decoded.
+    pub fn from_parquet_filtered(
+        infile: &str,
+        columns: Option<&[&str]>,
+        time_range: Option<(i64, i64)>,
+        time_column: Option<&str>,
+    ) -> Result<Self> {
+        let file = File::open(infile)?;
+        let options = ArrowReaderOptions::new().with_page_index(time_range.is_some());
+        let builder = ParquetRecordBatchReaderBuilder::try_new_with_options(file, options)?;
+        debug!("Converted arrow schema is: {:#?}", builder.schema());
+
+        let reader = prune_parquet(builder, columns, time_range, time_column)?.build()?;
+        let schema = reader.schema();
+        let record_batches = reader.collect::<arrow::error::Result<Vec<_>>>()?;
+
+        debug!(
+            "Read {} batches of parquet from {}",
+            record_batches.len(),
+            infile
+        );
+
+        Ok(TimeSeriesData {
+            time_column: projected_time_column(&schema, time_column),
+            schema,
+            record_batches,
         })
     }
 
@@ -72,7 +240,7 @@ impl TimeSeriesData {
 
         let builder = csv::ReaderBuilder::new(schema_arc.clone())
             .with_format(format)
-            .with_batch_size(512);
+            .with_batch_size(BATCH_SIZE);
         let mut csv_reader = builder.build(file)?;
 
         let mut record_batches = Vec::new();
@@ -90,9 +258,213 @@ impl TimeSeriesData {
         Ok(TimeSeriesData {
             schema: schema_arc,
             record_batches,
+            time_column: None,
         })
     }
 
+    /// Streams the record batches of a Parquet object in S3 without downloading it first.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The S3 client to read the object with.
+    /// * `uri` - The `s3://bucket/key` uri of the Parquet object.
+    /// * `columns` - The names of the columns to read, or `None` to read every column.
+    /// * `time_range` - The start and end, in milliseconds, of the rows to keep, or `None` to keep every row.
+    /// * `time_column` - The column `time_range` applies to, or `None` to find it like `time_column` does.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesStream` of the object's record batches or an error if
+    /// its footer cannot be read or a column is not in its schema. Only the footer and the chunks of
+    /// the selected columns are fetched, with range requests, as the stream is polled, skipping the
+    /// row groups and pages outside `time_range` like `from_parquet_filtered`.
+    pub async fn stream_parquet_from_s3(
+        client: &Client,
+        uri: &str,
+        columns: Option<&[&str]>,
+        time_range: Option<(i64, i64)>,
+        time_column: Option<&str>,
+    ) -> Result<TimeSeriesStream> {
+        let reader = s3::S3ObjectReader::new(client, uri).await?;
+        debug!("Streaming parquet from {}", uri);
+        parquet_stream(reader, columns, time_range, time_column).await
+    }
+
+    /// Streams the record batches of a CSV object in S3 without downloading it first.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The S3 client to read the object with.
+    /// * `uri` - The `s3://bucket/key` uri of the CSV object.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesStream` of the object's record batches or an error if
+    /// the object cannot be read. Like `from_csv`, the first row must hold the headers; the schema is
+    /// inferred from the first MiB of the object. An object whose name ends in `.gz` is gunzipped as
+    /// it streams.
+    pub async fn stream_csv_from_s3(client: &Client, uri: &str) -> Result<TimeSeriesStream> {
+        stream_text_object(client, uri, TextFormat::Csv, is_gzipped(uri)).await
+    }
+
+    /// Streams the record batches of a newline delimited JSON object in S3 without downloading it first.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The S3 client to read the object with.
+    /// * `uri` - The `s3://bucket/key` uri of the NDJSON object, holding one JSON object per line.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesStream` of the object's record batches or an error if
+    /// the object cannot be read. The schema is inferred from the first MiB of the object. An object
+    /// whose name ends in `.gz`, like the part files of an Athena CTAS query, is gunzipped as it streams.
+    pub async fn stream_ndjson_from_s3(client: &Client, uri: &str) -> Result<TimeSeriesStream> {
+        stream_text_object(client, uri, TextFormat::Ndjson, is_gzipped(uri)).await
+    }
+
+    /// Streams the record batches of an object in S3, picking the reader from its extension.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The S3 client to read the object with.
+    /// * `uri` - The `s3://bucket/key` uri of a `.parquet`, `.csv`, `.json`, `.ndjson` or `.jsonl`
+    ///   object. Text objects may be gzipped with a further `.gz`, and a `.gz` object with no other
+    ///   extension, like the part files of an Athena CTAS query, is read as NDJSON.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesStream` of every column of the object or an error if
+    /// the extension is not one of the above or the object cannot be read. Gzipped objects are
+    /// gunzipped as they stream. A `.json` object may hold an array of objects, as in `from_json`,
+    /// so it is read whole before being decoded.
+    pub async fn stream_from_s3(client: &Client, uri: &str) -> Result<TimeSeriesStream> {
+        match object_format(uri)? {
+            ObjectFormat::Parquet => {
+                Self::stream_parquet_from_s3(client, uri, None, None, None).await
+            }
+            ObjectFormat::Text { format, gzipped } => {
+                stream_text_object(client, uri, format, gzipped).await
+            }
+            ObjectFormat::Json => {
+                let (bucket, key) = s3::parse_s3_uri(uri)?;
+                let document = unwrap_json_array(download_document(client, bucket, key).await?)?;
+                debug!("Read json from {}", uri);
+                text_stream(
+                    stream::iter([Ok(Bytes::from(document))]),
+                    TextFormat::Ndjson,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Constructs a `TimeSeriesData` instance from JSON on disk, inferring the schema from every record.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to a JSON file, or to a directory whose files are read as one dataset. Each
+    ///   file holds an array of objects or a sequence of objects, and may be gzip compressed.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesData` instance populated with the records or an error
+    /// if a file cannot be read or parsed. Nested objects are flattened into `parent.child` columns,
+    /// and string columns whose values are all timestamps are read as millisecond timestamps.
+    pub fn from_json(path: &str) -> Result<Self> {
+        let documents = read_json_documents(Path::new(path))?
+            .into_iter()
+            .map(unwrap_json_array)
+            .collect::<Result<Vec<_>>>()?;
+        from_ndjson_documents(documents)
+    }
+
+    /// Constructs a `TimeSeriesData` instance from newline delimited JSON on disk, inferring the schema
+    /// from every record.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to an NDJSON file, or to a directory whose files are read as one dataset,
+    ///   like the part files Athena writes for a CTAS query with `format = 'JSON'`. Files may be gzip
+    ///   compressed.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesData` instance populated with the records or an error
+    /// if a file cannot be read or parsed. Columns are flattened and parsed as in `from_json`.
+    pub fn from_ndjson(path: &str) -> Result<Self> {
+        from_ndjson_documents(read_json_documents(Path::new(path))?)
+    }
+
+    /// Constructs a `TimeSeriesData` instance from the output of an Athena CTAS query with
+    /// `format = 'JSON'`, such as `s3://metadata/<request_id>/`.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The S3 client to read the part files with.
+    /// * `uri` - The `s3://bucket/prefix/` uri the query wrote its table to.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesData` instance with the records of every part file under
+    /// the prefix, read like `from_ndjson`, or an error if there are none or one cannot be read.
+    pub async fn from_ctas_output(client: &Client, uri: &str) -> Result<Self> {
+        let (bucket, prefix) = s3::parse_s3_uri(uri)?;
+        let keys: Vec<String> = s3::list_objects_with_prefix(client, bucket, prefix)
+            .await?
+            .into_iter()
+            .filter(|key| is_part_file(key.rsplit('/').next().unwrap_or_default()))
+            .collect();
+        if keys.is_empty() {
+            return Err(eyre::eyre!("No part files under {}", uri));
+        }
+
+        let mut documents = Vec::with_capacity(keys.len());
+        for key in &keys {
+            documents.push(download_document(client, bucket, key).await?);
+        }
+        debug!("Read {} part files from {}", keys.len(), uri);
+
+        from_ndjson_documents(documents)
+    }
+
+    /// Uses `name` as the time column instead of detecting it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of a timestamp, Date32 or Date64 column.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either the `TimeSeriesData` with its time column set, or an error if the
+    /// schema has no such column or it does not hold points in time.
+    pub fn with_time_column(mut self, name: &str) -> Result<Self> {
+        let field = self
+            .schema
+            .field_with_name(name)
+            .map_err(|_| eyre::eyre!("Time column {} not found", name))?;
+        if !is_time_point(field.data_type()) {
+            return Err(eyre::eyre!(
+                "Column {} of type {} cannot be used as the time column",
+                name,
+                field.data_type()
+            ));
+        }
+        self.time_column = Some(name.to_string());
+        Ok(self)
+    }
+
+    /// Finds the time column: the one named through `with_time_column`, else the one named by the
+    /// schema's `TIME_COLUMN_KEY` metadata, else the first timestamp, Date32 or Date64 column.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either the index and field of the time column, or an error if the
+    /// named column is missing or not a point in time, or no column qualifies.
+    pub fn time_column(&self) -> Result<(usize, &Field)> {
+        find_time_column(&self.schema, self.time_column.as_deref())
+    }
+
     /// Writes the contained `TimeSeriesData` to disk in Parquet format.
     ///
     /// # Arguments
@@ -123,11 +495,12 @@ impl TimeSeriesData {
         Ok(())
     }
 
-    /// Filters the record batches based on a provided filter function, supporting dynamic identification of temporal fields.
+    /// Filters the record batches based on a provided filter function applied to the time column.
     ///
     /// # Arguments
     ///
-    /// * `filter_fn` - A closure that takes a timestamp in milliseconds and returns a boolean indicating whether the record should be included.
+    /// * `filter_fn` - A closure that takes a timestamp in milliseconds since the epoch (UTC for zoned
+    ///   timestamps, truncated for finer units) and returns a boolean indicating whether the record should be included.
     ///
     /// # Returns
     ///
@@ -137,36 +510,27 @@ impl TimeSeriesData {
     where
         F: Fn(i64) -> bool + Copy,
     {
+        let (time_index, _) = self.time_column()?;
         let filtered_batches: Vec<RecordBatch> = self
             .record_batches
             .iter()
             .map(|batch| {
-                let temporal_field_index = batch
-                    .schema()
-                    .fields()
-                    .iter()
-                    .position(|field| field.data_type().is_temporal())
-                    .ok_or_else(|| eyre::eyre!("Temporal column not found"))?;
-
-                let temporal_col = batch
-                    .column(temporal_field_index)
-                    .as_any()
-                    .downcast_ref::<TimestampMillisecondArray>()
-                    .ok_or_else(|| eyre::eyre!("Temporal column has incorrect type"))?;
+                let times = epoch_millis(batch.column(time_index))?;
 
-                let mask = temporal_col
+                let mask = times
                     .iter()
-                    .map(|maybe_time| maybe_time.map(|time| filter_fn(time)))
+                    .map(|maybe_time| maybe_time.map(filter_fn))
                     .collect::<BooleanArray>();
 
                 filter_record_batch(batch, &mask)
-                    .map_err(|_| eyre::eyre!("Failed to filter record batch"))
+                    .map_err(|e| eyre::eyre!("Failed to filter record batch: {}", e))
             })
             .collect::<Result<Vec<RecordBatch>>>()?;
 
         Ok(TimeSeriesData {
             schema: self.schema.clone(),
             record_batches: filtered_batches,
+            time_column: self.time_column.clone(),
         })
     }
 
@@ -186,7 +550,9 @@ impl TimeSeriesData {
     }
 
     /// Filters the record batches to adjust the time between sampled events according to the specified granularity,
-    /// taking into account the datatype of the temporal column for granularity adjustment.
+    /// comparing it against the time column in milliseconds whatever its unit.
+    /// Only rows that fall exactly on a multiple of the granularity are kept, so irregularly sampled data
+    /// is better summarised with `resample`.
     ///
     /// # Arguments
     ///
@@ -197,35 +563,129 @@ impl TimeSeriesData {
     /// A result containing either a new `TimeSeriesData` instance with the filtered record batches
     /// or an error if the operation fails.
     pub fn filter_by_granularity(&self, granularity: i64) -> Result<Self> {
-        let data_type = self
+        if granularity <= 0 {
+            return Err(eyre::eyre!(
+                "Granularity must be positive, got {}ms",
+                granularity
+            ));
+        }
+        self.filter_record_batches(move |time| time % granularity == 0)
+    }
+
+    /// Groups the rows into buckets of `granularity` milliseconds by their temporal column and
+    /// aggregates every numeric column per bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `granularity` - The width of each bucket in milliseconds.
+    /// * `aggregations` - The aggregations computed for each numeric column.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a new `TimeSeriesData` instance with one row per non-empty bucket,
+    /// in time order, or an error if the operation fails. The temporal column keeps its name and
+    /// holds the start of each bucket as a millisecond timestamp, keeping any timezone. Each numeric column `c` becomes a column
+    /// `c_<aggregation>` per aggregation, `Float64` except for the `UInt64` counts; nulls are
+    /// skipped, and a bucket without values for `c` has null aggregates. Rows without a time and
+    /// columns that are neither temporal nor numeric are dropped.
+    pub fn resample(&self, granularity: i64, aggregations: &[Aggregation]) -> Result<Self> {
+        if granularity <= 0 {
+            return Err(eyre::eyre!(
+                "Granularity must be positive, got {}ms",
+                granularity
+            ));
+        }
+        let mut unique: Vec<Aggregation> = Vec::new();
+        for aggregation in aggregations {
+            if !unique.contains(aggregation) {
+                unique.push(*aggregation);
+            }
+        }
+
+        let (time_index, time_field) = self.time_column()?;
+        let time_type = millis_type(time_field.data_type())?;
+        let value_fields: Vec<(usize, &Arc<Field>)> = self
             .schema
             .fields()
             .iter()
-            .find_map(|field| {
-                if field.data_type().is_temporal() {
-                    Some(field.data_type())
-                } else {
-                    None
+            .enumerate()
+            .filter(|(_, field)| field.data_type().is_numeric())
+            .collect();
+
+        let mut buckets: BTreeMap<i64, Vec<Bucket>> = BTreeMap::new();
+        for batch in &self.record_batches {
+            let times = epoch_millis(batch.column(time_index))?;
+            let values = value_fields
+                .iter()
+                .map(|(index, _)| cast(batch.column(*index), &DataType::Float64))
+                .collect::<std::result::Result<Vec<ArrayRef>, _>>()?;
+            let values: Vec<&Float64Array> = values
+                .iter()
+                .map(|values| values.as_primitive::<Float64Type>())
+                .collect();
+
+            for (row, time) in times.iter().enumerate() {
+                let Some(time) = time else { continue };
+                let start = time.div_euclid(granularity) * granularity;
+                let bucket = buckets
+                    .entry(start)
+                    .or_insert_with(|| vec![Bucket::default(); value_fields.len()]);
+                for (column, values) in values.iter().enumerate() {
+                    if values.is_valid(row) {
+                        bucket[column].add(time, values.value(row));
+                    }
                 }
-            })
-            .expect("Temporal column not found");
-
-        let conversion_factor = match data_type {
-            DataType::Timestamp(unit, _) => match unit {
-                TimeUnit::Second => 1_000,
-                TimeUnit::Millisecond => 1,
-                TimeUnit::Microsecond => 1, // just going to ignore <ms, lol
-                TimeUnit::Nanosecond => 1,
-            },
-            DataType::Date32 => 86_400_000, // days to ms
-            DataType::Date64 => 1,          // represented in ms
-            _ => unimplemented!("Unsupported temporal type"),
-        };
+            }
+        }
+
+        let mut fields = vec![Field::new(time_field.name(), time_type.clone(), false)];
+        let starts = TimestampMillisecondArray::from(buckets.keys().copied().collect::<Vec<_>>());
+        let mut columns: Vec<ArrayRef> = vec![cast(&(Arc::new(starts) as ArrayRef), &time_type)?];
+        for (column, (_, field)) in value_fields.iter().enumerate() {
+            for aggregation in unique.iter().copied() {
+                let name = format!("{}_{}", field.name(), aggregation.name());
+                let aggregated = buckets.values().map(|bucket| &bucket[column]);
+                let array: ArrayRef = if aggregation == Aggregation::Count {
+                    fields.push(Field::new(name, DataType::UInt64, false));
+                    Arc::new(UInt64Array::from_iter_values(
+                        aggregated.map(|bucket| bucket.count),
+                    ))
+                } else {
+                    fields.push(Field::new(name, DataType::Float64, true));
+                    Arc::new(Float64Array::from_iter(
+                        aggregated.map(|bucket| bucket.value(aggregation)),
+                    ))
+                };
+                columns.push(array);
+            }
+        }
 
-        // Adjust granularity based on the data type
-        let adjusted_granularity = granularity * conversion_factor;
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        debug!(
+            "Resampled {} rows into {} buckets of {}ms",
+            self.record_batches
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>(),
+            batch.num_rows(),
+            granularity
+        );
+
+        Ok(TimeSeriesData {
+            schema,
+            record_batches: vec![batch],
+            time_column: Some(time_field.name().clone()),
+        })
+    }
 
-        self.filter_record_batches(move |time| time % adjusted_granularity == 0)
+    /// Resamples the data at the granularity `request` asked for, see `resample`.
+    pub fn resample_for_request(
+        &self,
+        request: &JobRequest,
+        aggregations: &[Aggregation],
+    ) -> Result<Self> {
+        self.resample(request.granularity.into(), aggregations)
     }
 
     /// Retrieves a list of field names from the schema.
@@ -272,11 +732,722 @@ impl TimeSeriesData {
     }
 }
 
+/// Record batches that are read as the stream is polled, so a dataset never has to fit in memory.
+/// `collect` gathers them into a `TimeSeriesData` when it does.
+pub struct TimeSeriesStream {
+    schema: Arc<Schema>,
+    batches: BoxStream<'static, Result<RecordBatch>>,
+    time_column: Option<String>, // carried over to the `TimeSeriesData` of `collect`
+}
+
+impl TimeSeriesStream {
+    /// The schema of every batch in the stream.
+    pub fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    /// Reads the remaining batches into memory.
+    ///
+    /// # Returns
+    ///
+    /// A result containing either a `TimeSeriesData` holding the batches or the first error the
+    /// stream yields.
+    pub async fn collect(self) -> Result<TimeSeriesData> {
+        let record_batches: Vec<RecordBatch> = self.batches.try_collect().await?;
+        Ok(TimeSeriesData {
+            schema: self.schema,
+            record_batches,
+            time_column: self.time_column,
+        })
+    }
+}
+
+impl Stream for TimeSeriesStream {
+    type Item = Result<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.batches.poll_next_unpin(cx)
+    }
+}
+
+/// Streams the Parquet file read through `input`, keeping only `columns` and the rows in
+/// `time_range` of `time_column` when given.
+async fn parquet_stream<T>(
+    input: T,
+    columns: Option<&[&str]>,
+    time_range: Option<(i64, i64)>,
+    time_column: Option<&str>,
+) -> Result<TimeSeriesStream>
+where
+    T: AsyncFileReader + Unpin + Send + 'static,
+{
+    let options = ArrowReaderOptions::new().with_page_index(time_range.is_some());
+    let builder = ParquetRecordBatchStreamBuilder::new_with_options(input, options).await?;
+    debug!("Converted arrow schema is: {:#?}", builder.schema());
+
+    let batches = prune_parquet(builder, columns, time_range, time_column)?.build()?;
+    Ok(TimeSeriesStream {
+        schema: batches.schema().clone(),
+        time_column: projected_time_column(batches.schema(), time_column),
+        batches: batches.map_err(eyre::Report::from).boxed(),
+    })
+}
+
+/// `time_column` when the read kept it. A time range can be applied to a column that is not
+/// among the columns read, and the data has no time column of its own then.
+fn projected_time_column(schema: &Schema, time_column: Option<&str>) -> Option<String> {
+    time_column
+        .filter(|name| schema.index_of(name).is_ok())
+        .map(str::to_string)
+}
+
+/// Narrows a Parquet read to `columns` and to the rows in `time_range` (inclusive, in milliseconds)
+/// of `time_column`, or of the column `find_time_column` picks. Row groups whose time column
+/// statistics are outside the range are skipped, and so are pages when the file has a page index;
+/// the rows left are filtered on the time column as they are decoded, before any other column is.
+fn prune_parquet<T>(
+    mut builder: ArrowReaderBuilder<T>,
+    columns: Option<&[&str]>,
+    time_range: Option<(i64, i64)>,
+    time_column: Option<&str>,
+) -> Result<ArrowReaderBuilder<T>> {
+    if let Some(columns) = columns {
+        let indices = columns
+            .iter()
+            .map(|name| builder.schema().index_of(name))
+            .collect::<arrow::error::Result<Vec<_>>>()?;
+        let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+        builder = builder.with_projection(mask);
+    }
+
+    let Some((start_time, end_time)) = time_range else {
+        return Ok(builder);
+    };
+    let (root, field) = find_time_column(builder.schema(), time_column)?;
+    let leaf = (0..builder.parquet_schema().num_columns())
+        .find(|&leaf| builder.parquet_schema().get_column_root_idx(leaf) == root)
+        .ok_or_else(|| eyre::eyre!("Time column {} not found in parquet", field.name()))?;
+
+    let metadata = builder.metadata().clone();
+    let scale = millis_scale(
+        metadata
+            .file_metadata()
+            .schema_descr()
+            .column(leaf)
+            .as_ref(),
+    );
+    let row_groups = prune_row_groups(&metadata, leaf, scale, start_time, end_time);
+    debug!(
+        "Pruned {} of {} row groups outside {}..={} ms",
+        metadata.num_row_groups() - row_groups.len(),
+        metadata.num_row_groups(),
+        start_time,
+        end_time
+    );
+    if let Some(selection) = select_pages(&metadata, &row_groups, leaf, scale, start_time, end_time)
+    {
+        builder = builder.with_row_selection(selection);
+    }
+
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [root]);
+    let predicate = ArrowPredicateFn::new(mask, move |batch: RecordBatch| {
+        let times = epoch_millis(batch.column(0))
+            .map_err(|e| arrow::error::ArrowError::ExternalError(e.into()))?;
+        Ok(times
+            .iter()
+            .map(|time| time.map(|time| time >= start_time && time <= end_time))
+            .collect::<BooleanArray>())
+    });
+
+    Ok(builder
+        .with_row_groups(row_groups)
+        .with_row_filter(RowFilter::new(vec![Box::new(predicate)])))
+}
+
+/// How the statistics of a time column convert to milliseconds since the epoch, as a multiplier
+/// and a divisor, or `None` when its physical values are not in a unit we know.
+fn millis_scale(column: &ColumnDescriptor) -> Option<(i64, i64)> {
+    match (column.logical_type(), column.converted_type()) {
+        (Some(LogicalType::Date), _) | (None, ConvertedType::DATE) => Some((86_400_000, 1)),
+        (Some(LogicalType::Timestamp { unit, .. }), _) => match unit {
+            ParquetTimeUnit::MILLIS(_) => Some((1, 1)),
+            ParquetTimeUnit::MICROS(_) => Some((1, 1_000)),
+            ParquetTimeUnit::NANOS(_) => Some((1, 1_000_000)),
+        },
+        (None, ConvertedType::TIMESTAMP_MILLIS) => Some((1, 1)),
+        (None, ConvertedType::TIMESTAMP_MICROS) => Some((1, 1_000)),
+        _ => None,
+    }
+}
+
+/// Whether any time between the physical values `min` and `max` can be in the range. The bounds
+/// are rounded outwards to whole milliseconds, so a range is only ruled out when it certainly misses.
+fn may_overlap(
+    min: i64,
+    max: i64,
+    (multiplier, divisor): (i64, i64),
+    start_time: i64,
+    end_time: i64,
+) -> bool {
+    let min = min.div_euclid(divisor).saturating_mul(multiplier);
+    let max = (-(-max).div_euclid(divisor)).saturating_mul(multiplier);
+    max >= start_time && min <= end_time
+}
+
+/// The row groups whose time column statistics overlap the range. Row groups without statistics
+/// are kept.
+fn prune_row_groups(
+    metadata: &ParquetMetaData,
+    leaf: usize,
+    scale: Option<(i64, i64)>,
+    start_time: i64,
+    end_time: i64,
+) -> Vec<usize> {
+    (0..metadata.num_row_groups())
+        .filter(|&row_group| {
+            let statistics = metadata.row_group(row_group).column(leaf).statistics();
+            let bounds = match statistics {
+                Some(Statistics::Int32(s)) if s.has_min_max_set() => {
+                    Some((*s.min() as i64, *s.max() as i64))
+                }
+                Some(Statistics::Int64(s)) if s.has_min_max_set() => Some((*s.min(), *s.max())),
+                _ => None,
+            };
+            match (bounds, scale) {
+                (Some((min, max)), Some(scale)) => {
+                    may_overlap(min, max, scale, start_time, end_time)
+                }
+                _ => true,
+            }
+        })
+        .collect()
+}
+
+/// Selects the rows of the pages in `row_groups` whose time column overlaps the range, or `None`
+/// when the file has no page index.
+fn select_pages(
+    metadata: &ParquetMetaData,
+    row_groups: &[usize],
+    leaf: usize,
+    scale: Option<(i64, i64)>,
+    start_time: i64,
+    end_time: i64,
+) -> Option<RowSelection> {
+    let scale = scale?;
+    let column_index = metadata.column_index()?;
+    let offset_index = metadata.offset_index()?;
+
+    let mut selectors = Vec::new();
+    let mut skipped = 0;
+    for &row_group in row_groups {
+        let num_rows = metadata.row_group(row_group).num_rows() as usize;
+        let pages = &offset_index[row_group][leaf];
+        let bounds: Vec<Option<(i64, i64)>> = match &column_index[row_group][leaf] {
+            Index::INT32(index) => index
+                .indexes
+                .iter()
+                .map(|page| Some((*page.min.as_ref()? as i64, *page.max.as_ref()? as i64)))
+                .collect(),
+            Index::INT64(index) => index
+                .indexes
+                .iter()
+                .map(|page| Some((*page.min.as_ref()?, *page.max.as_ref()?)))
+                .collect(),
+            _ => vec![None; pages.len()],
+        };
+        for (page, location) in pages.iter().enumerate() {
+            let first_row = location.first_row_index as usize;
+            let end_row = pages
+                .get(page + 1)
+                .map_or(num_rows, |next| next.first_row_index as usize);
+            let keep = bounds
+                .get(page)
+                .copied()
+                .flatten()
+                .is_none_or(|(min, max)| may_overlap(min, max, scale, start_time, end_time));
+            if keep {
+                selectors.push(RowSelector::select(end_row - first_row));
+            } else {
+                selectors.push(RowSelector::skip(end_row - first_row));
+                skipped += 1;
+            }
+        }
+    }
+
+    debug!(
+        "Skipping {} pages outside {}..={} ms",
+        skipped, start_time, end_time
+    );
+    Some(RowSelection::from(selectors))
+}
+
+/// Whether `name` is a data file rather than a marker like `_SUCCESS` or a hidden file.
+fn is_part_file(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('_') && !name.starts_with('.')
+}
+
+/// The decompressed contents of the JSON file at `path`, or of every part file in the directory at
+/// `path` in name order.
+fn read_json_documents(path: &Path) -> Result<Vec<Vec<u8>>> {
+    if !path.is_dir() {
+        return Ok(vec![decompress(fs::read(path)?)?]);
+    }
+
+    let mut files = fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    files.retain(|file| {
+        file.is_file()
+            && is_part_file(
+                file.file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or_default(),
+            )
+    });
+    files.sort();
+    debug!("Reading {} part files from {}", files.len(), path.display());
+
+    files
+        .into_iter()
+        .map(|file| decompress(fs::read(file)?))
+        .collect()
+}
+
+/// The first bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Gunzips `contents` when they start with the gzip magic number, whatever the file is called.
+fn decompress(contents: Vec<u8>) -> Result<Vec<u8>> {
+    if !contents.starts_with(&GZIP_MAGIC) {
+        return Ok(contents);
+    }
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(contents.as_slice()).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Downloads `s3://bucket/key` through `read_document`, so the compressed object is never held
+/// whole.
+async fn download_document(client: &Client, bucket: &str, key: &str) -> Result<Vec<u8>> {
+    read_document(s3::stream_object(client, bucket, key).await?).await
+}
+
+/// Reads `body` into memory, gunzipping its chunks as they arrive when it starts with the gzip
+/// magic number.
+async fn read_document<S>(mut body: S) -> Result<Vec<u8>>
+where
+    S: Stream<Item = Result<Bytes>> + Unpin,
+{
+    let mut head = Vec::new();
+    let mut gunzip: Option<flate2::write::MultiGzDecoder<Vec<u8>>> = None;
+    while let Some(chunk) = body.try_next().await? {
+        match &mut gunzip {
+            Some(gunzip) => gunzip.write_all(&chunk)?,
+            None => {
+                head.extend_from_slice(&chunk);
+                if head.starts_with(&GZIP_MAGIC) {
+                    let mut decoder = flate2::write::MultiGzDecoder::new(Vec::new());
+                    decoder.write_all(&std::mem::take(&mut head))?;
+                    gunzip = Some(decoder);
+                }
+            }
+        }
+    }
+    match gunzip {
+        Some(gunzip) => Ok(gunzip.finish()?),
+        None => Ok(head),
+    }
+}
+
+/// Rewrites a JSON document holding an array of objects as one object per line.
+fn unwrap_json_array(document: Vec<u8>) -> Result<Vec<u8>> {
+    if document.iter().find(|byte| !byte.is_ascii_whitespace()) != Some(&b'[') {
+        return Ok(document);
+    }
+    let records: Vec<serde_json::Value> = serde_json::from_slice(&document)?;
+    let mut lines = Vec::with_capacity(document.len());
+    for record in records {
+        serde_json::to_writer(&mut lines, &record)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Reads `documents` of newline delimited JSON as one dataset, with a schema inferred from all of
+/// them, nested structs flattened and timestamp strings parsed. The documents are parsed one after
+/// another rather than copied into one.
+fn from_ndjson_documents(documents: Vec<Vec<u8>>) -> Result<TimeSeriesData> {
+    let values = documents.iter().flat_map(|document| {
+        serde_json::Deserializer::from_slice(document)
+            .into_iter::<serde_json::Value>()
+            .map(|value| value.map_err(|e| arrow::error::ArrowError::JsonError(e.to_string())))
+    });
+    let schema = Arc::new(arrow::json::reader::infer_json_schema_from_iterator(
+        values,
+    )?);
+    let mut decoder = arrow::json::ReaderBuilder::new(schema.clone())
+        .with_batch_size(BATCH_SIZE)
+        .build_decoder()?;
+
+    let mut record_batches = Vec::new();
+    for document in &documents {
+        let mut buf = document.as_slice();
+        while !buf.is_empty() {
+            let decoded = decoder.decode(buf)?;
+            buf = &buf[decoded..];
+            // a full batch makes the decoder stop decoding until flushed
+            if !buf.is_empty() {
+                if let Some(batch) = decoder.flush()? {
+                    record_batches.push(flatten_structs(&batch)?);
+                }
+            }
+        }
+    }
+    if let Some(batch) = decoder.flush()? {
+        record_batches.push(flatten_structs(&batch)?);
+    }
+    let mut schema = flatten_structs(&RecordBatch::new_empty(schema))?.schema();
+
+    let timestamp = DataType::Timestamp(TimeUnit::Millisecond, None);
+    let strict = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    for index in 0..schema.fields().len() {
+        if schema.field(index).data_type() != &DataType::Utf8 {
+            continue;
+        }
+        let columns: Vec<&ArrayRef> = record_batches
+            .iter()
+            .map(|batch| batch.column(index))
+            .collect();
+        if columns
+            .iter()
+            .all(|column| column.null_count() == column.len())
+        {
+            continue;
+        }
+        let parsed = columns
+            .iter()
+            .map(|column| cast_with_options(column, &timestamp, &strict))
+            .collect::<arrow::error::Result<Vec<_>>>();
+        let Ok(parsed) = parsed else { continue };
+        debug!(
+            "Parsing column {} as timestamps",
+            schema.field(index).name()
+        );
+
+        let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        fields[index] = fields[index].clone().with_data_type(timestamp.clone());
+        schema = Arc::new(Schema::new(fields));
+        record_batches = record_batches
+            .iter()
+            .zip(parsed)
+            .map(|(batch, column)| {
+                let mut columns = batch.columns().to_vec();
+                columns[index] = column;
+                RecordBatch::try_new(schema.clone(), columns)
+            })
+            .collect::<arrow::error::Result<Vec<_>>>()?;
+    }
+
+    Ok(TimeSeriesData {
+        schema,
+        record_batches,
+        time_column: None,
+    })
+}
+
+/// Replaces every struct column of `batch` with a column per leaf field, named `parent.child`.
+/// A leaf is null wherever one of its parents is.
+fn flatten_structs(batch: &RecordBatch) -> Result<RecordBatch> {
+    let mut fields = Vec::new();
+    let mut columns = Vec::new();
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        flatten_column(
+            field.name().clone(),
+            field,
+            column.clone(),
+            &mut fields,
+            &mut columns,
+        )?;
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+fn flatten_column(
+    name: String,
+    field: &Field,
+    column: ArrayRef,
+    fields: &mut Vec<Field>,
+    columns: &mut Vec<ArrayRef>,
+) -> Result<()> {
+    let DataType::Struct(children) = field.data_type() else {
+        fields.push(field.clone().with_name(name));
+        columns.push(column);
+        return Ok(());
+    };
+
+    let parent = column.as_struct();
+    for (child_field, child) in children.iter().zip(parent.columns()) {
+        let nulls = NullBuffer::union(parent.nulls(), child.nulls());
+        let child = make_array(child.to_data().into_builder().nulls(nulls).build()?);
+        let child_field = child_field
+            .as_ref()
+            .clone()
+            .with_nullable(field.is_nullable() || child_field.is_nullable());
+        flatten_column(
+            format!("{}.{}", name, child_field.name()),
+            &child_field,
+            child,
+            fields,
+            columns,
+        )?;
+    }
+    Ok(())
+}
+
+/// How `stream_from_s3` reads an object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectFormat {
+    Parquet,
+    Text { format: TextFormat, gzipped: bool },
+    Json, // read whole, as it may hold an array of objects
+}
+
+/// Whether the object at `uri` is gzipped, going by a final `.gz` on its name.
+fn is_gzipped(uri: &str) -> bool {
+    uri.rsplit('/').next().unwrap_or_default().ends_with(".gz")
+}
+
+/// Tells how to read the object at `uri` from the extension of its name, under a final `.gz`.
+fn object_format(uri: &str) -> Result<ObjectFormat> {
+    let name = uri.rsplit('/').next().unwrap_or_default();
+    let gzipped = is_gzipped(uri);
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+    match (extension, gzipped) {
+        (Some("parquet"), false) => Ok(ObjectFormat::Parquet),
+        (Some("parquet"), true) => Err(eyre::eyre!("Cannot stream gzipped parquet {}", uri)),
+        (Some("csv"), _) => Ok(ObjectFormat::Text {
+            format: TextFormat::Csv,
+            gzipped,
+        }),
+        (Some("ndjson" | "jsonl"), _) | (None, true) => Ok(ObjectFormat::Text {
+            format: TextFormat::Ndjson,
+            gzipped,
+        }),
+        (Some("json"), _) => Ok(ObjectFormat::Json),
+        _ => Err(eyre::eyre!(
+            "Cannot tell the format of {} from its extension",
+            uri
+        )),
+    }
+}
+
+/// Streams the text object at `uri` as `format`, gunzipping it as it arrives when `gzipped`.
+async fn stream_text_object(
+    client: &Client,
+    uri: &str,
+    format: TextFormat,
+    gzipped: bool,
+) -> Result<TimeSeriesStream> {
+    let (bucket, key) = s3::parse_s3_uri(uri)?;
+    let body = s3::stream_object(client, bucket, key).await?;
+    debug!("Streaming {:?} from {}", format, uri);
+    if gzipped {
+        text_stream(gunzip_stream(body), format).await
+    } else {
+        text_stream(body, format).await
+    }
+}
+
+/// Gunzips `body` as its chunks arrive.
+fn gunzip_stream<S>(body: S) -> BoxStream<'static, Result<Bytes>>
+where
+    S: Stream<Item = Result<Bytes>> + Unpin + Send + 'static,
+{
+    let decoder = flate2::write::MultiGzDecoder::new(Vec::new());
+    stream::try_unfold((body, Some(decoder)), |(mut body, decoder)| async move {
+        let Some(mut decoder) = decoder else {
+            return Ok(None);
+        };
+        match body.try_next().await? {
+            Some(chunk) => {
+                decoder.write_all(&chunk)?;
+                decoder.flush()?;
+                let decompressed = Bytes::from(std::mem::take(decoder.get_mut()));
+                Ok(Some((decompressed, (body, Some(decoder)))))
+            }
+            None => Ok(Some((Bytes::from(decoder.finish()?), (body, None)))),
+        }
+    })
+    .boxed()
+}
+
+/// The line based text formats `text_stream` decodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextFormat {
+    Csv,
+    Ndjson,
+}
+
+enum TextDecoder {
+    Csv(Box<arrow_csv::reader::Decoder>),
+    Ndjson(arrow::json::reader::Decoder),
+}
+
+impl TextDecoder {
+    fn decode(&mut self, buf: &[u8]) -> arrow::error::Result<usize> {
+        match self {
+            TextDecoder::Csv(decoder) => decoder.decode(buf),
+            TextDecoder::Ndjson(decoder) => decoder.decode(buf),
+        }
+    }
+
+    fn flush(&mut self) -> arrow::error::Result<Option<RecordBatch>> {
+        match self {
+            TextDecoder::Csv(decoder) => decoder.flush(),
+            TextDecoder::Ndjson(decoder) => decoder.flush(),
+        }
+    }
+}
+
+/// Decodes `body` as `format` into record batches as its chunks arrive. The schema is inferred
+/// from the complete lines of its first `SCHEMA_SAMPLE_BYTES`.
+async fn text_stream<S>(mut body: S, format: TextFormat) -> Result<TimeSeriesStream>
+where
+    S: Stream<Item = Result<Bytes>> + Unpin + Send + 'static,
+{
+    let mut head = Vec::new();
+    let mut done = false;
+    while head.len() < SCHEMA_SAMPLE_BYTES && !done {
+        match body.try_next().await? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => done = true,
+        }
+    }
+    let sample = match head.iter().rposition(|&byte| byte == b'\n') {
+        Some(end) if !done => &head[..=end],
+        _ => &head[..],
+    };
+
+    let (schema, decoder) = match format {
+        TextFormat::Csv => {
+            let csv_format = Format::default().with_header(true);
+            let (schema, _) = csv_format.infer_schema(Cursor::new(sample), None)?;
+            let schema = Arc::new(schema);
+            let decoder = csv::ReaderBuilder::new(schema.clone())
+                .with_format(csv_format)
+                .with_batch_size(BATCH_SIZE)
+                .build_decoder();
+            (schema, TextDecoder::Csv(Box::new(decoder)))
+        }
+        TextFormat::Ndjson => {
+            let (schema, _) = arrow::json::reader::infer_json_schema(Cursor::new(sample), None)?;
+            let schema = Arc::new(schema);
+            let decoder = arrow::json::ReaderBuilder::new(schema.clone())
+                .with_batch_size(BATCH_SIZE)
+                .build_decoder()?;
+            (schema, TextDecoder::Ndjson(decoder))
+        }
+    };
+
+    let state = (decoder, Bytes::from(head), body, done);
+    let batches = stream::try_unfold(
+        state,
+        |(mut decoder, mut buffered, mut body, mut done)| async move {
+            loop {
+                while buffered.is_empty() && !done {
+                    match body.try_next().await? {
+                        Some(chunk) => buffered = chunk,
+                        None => done = true,
+                    }
+                }
+                // an empty slice tells the decoder that the last record has ended; a full batch
+                // makes it stop decoding until flushed
+                let decoded = decoder.decode(&buffered)?;
+                buffered.advance(decoded);
+                if decoded > 0 {
+                    continue;
+                }
+                match decoder.flush()? {
+                    Some(batch) => return Ok(Some((batch, (decoder, buffered, body, done)))),
+                    None if done => return Ok(None),
+                    None => {}
+                }
+            }
+        },
+    );
+
+    Ok(TimeSeriesStream {
+        schema,
+        batches: batches.boxed(),
+        time_column: None,
+    })
+}
+
+/// Finds the time column of `schema`: the column `named`, else the one named in the schema
+/// metadata under `TIME_COLUMN_KEY`, else the first timestamp, Date32 or Date64 column.
+fn find_time_column<'a>(schema: &'a Schema, named: Option<&str>) -> Result<(usize, &'a Field)> {
+    let named = named.or_else(|| schema.metadata().get(TIME_COLUMN_KEY).map(String::as_str));
+    let (index, field) = match named {
+        Some(name) => schema
+            .fields()
+            .find(name)
+            .ok_or_else(|| eyre::eyre!("Time column {} not found", name))?,
+        None => schema
+            .fields()
+            .iter()
+            .enumerate()
+            .find(|(_, field)| is_time_point(field.data_type()))
+            .ok_or_else(|| eyre::eyre!("Temporal column not found"))?,
+    };
+    if !is_time_point(field.data_type()) {
+        return Err(eyre::eyre!(
+            "Column {} of type {} cannot be used as the time column",
+            field.name(),
+            field.data_type()
+        ));
+    }
+    Ok((index, field.as_ref()))
+}
+
+/// Whether a column of `data_type` holds points in time that a time column can be made of.
+fn is_time_point(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64
+    )
+}
+
+/// The millisecond timestamp type a time column of `data_type` is read as, keeping its timezone.
+fn millis_type(data_type: &DataType) -> Result<DataType> {
+    match data_type {
+        DataType::Timestamp(_, tz) => Ok(DataType::Timestamp(TimeUnit::Millisecond, tz.clone())),
+        DataType::Date32 | DataType::Date64 => Ok(DataType::Timestamp(TimeUnit::Millisecond, None)),
+        other => Err(eyre::eyre!("Unsupported time column type {}", other)),
+    }
+}
+
+/// The values of a time column as milliseconds since the epoch. Zoned timestamps are stored in
+/// UTC, so their timezone does not change the values.
+fn epoch_millis(column: &ArrayRef) -> Result<TimestampMillisecondArray> {
+    let millis = cast(column, &millis_type(column.data_type())?)?;
+    Ok(millis.as_primitive::<TimestampMillisecondType>().clone())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use arrow::{
-        array::{ArrayRef, Date32Array, Float64Array, Int64Array},
+        array::{
+            ArrayRef, Date32Array, Date64Array, Float64Array, Int64Array, StringArray,
+            TimestampMicrosecondArray, TimestampNanosecondArray, TimestampSecondArray,
+        },
         datatypes::Field,
         util::pretty::print_batches,
     };
@@ -385,6 +1556,7 @@ mod tests {
         TimeSeriesData {
             schema: Arc::new(schema),
             record_batches: vec![batch],
+            time_column: None,
         }
     }
 
@@ -677,6 +1849,7 @@ mod tests {
         let ts_data = TimeSeriesData {
             schema: Arc::new(schema),
             record_batches: vec![batch],
+            time_column: None,
         };
 
         // Apply filtering based on the "maybe_a_time" column
@@ -698,4 +1871,666 @@ mod tests {
         assert_eq!(filtered_event_times.value(1), 3_000);
         assert_eq!(filtered_event_times.value(2), 4_000);
     }
+
+    fn float_column(data: &TimeSeriesData, name: &str) -> Vec<Option<f64>> {
+        let batch = &data.record_batches[0];
+        let index = batch.schema().index_of(name).unwrap();
+        batch
+            .column(index)
+            .as_primitive::<Float64Type>()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn resample_aggregates_irregular_samples_per_bucket() {
+        let ts_data = create_timeseries_data(
+            vec![Some(0), Some(300), Some(1_700), Some(2_900), Some(2_100)],
+            vec![1, 2, 3, 5, 4],
+        );
+
+        let resampled = ts_data.resample(1_000, &Aggregation::ALL).unwrap();
+
+        assert_eq!(resampled.record_batches.len(), 1);
+        let batch = &resampled.record_batches[0];
+        let starts = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(starts.values().to_vec(), vec![0, 1_000, 2_000]);
+        assert_eq!(
+            resampled.field_names(),
+            vec![
+                "timestamp",
+                "value_mean",
+                "value_min",
+                "value_max",
+                "value_sum",
+                "value_count",
+                "value_first",
+                "value_last",
+            ]
+        );
+
+        let column = |name| float_column(&resampled, name);
+        assert_eq!(column("value_mean"), vec![Some(1.5), Some(3.0), Some(4.5)]);
+        assert_eq!(column("value_min"), vec![Some(1.0), Some(3.0), Some(4.0)]);
+        assert_eq!(column("value_max"), vec![Some(2.0), Some(3.0), Some(5.0)]);
+        assert_eq!(column("value_sum"), vec![Some(3.0), Some(3.0), Some(9.0)]);
+        // first and last go by time, not by row order
+        assert_eq!(column("value_first"), vec![Some(1.0), Some(3.0), Some(4.0)]);
+        assert_eq!(column("value_last"), vec![Some(2.0), Some(3.0), Some(5.0)]);
+        let counts = batch
+            .column(5)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(counts.values().to_vec(), vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn resample_skips_nulls_and_drops_non_numeric_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Date32, false),
+            Field::new("label", DataType::Utf8, true),
+            Field::new("reading", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Date32Array::from(vec![0, 0, 1])) as ArrayRef,
+                Arc::new(StringArray::from(vec!["a", "b", "c"])) as ArrayRef,
+                Arc::new(Float64Array::from(vec![Some(1.0), None, None])) as ArrayRef,
+            ],
+        )
+        .unwrap();
+        let ts_data = TimeSeriesData {
+            schema,
+            record_batches: vec![batch],
+            time_column: None,
+        };
+
+        let day = 86_400_000;
+        let resampled = ts_data
+            .resample(
+                day,
+                &[Aggregation::Mean, Aggregation::Count, Aggregation::Mean],
+            )
+            .unwrap();
+
+        assert_eq!(
+            resampled.field_names(),
+            vec!["day", "reading_mean", "reading_count"]
+        );
+        assert_eq!(
+            resampled.field_type("day"),
+            Some(DataType::Timestamp(TimeUnit::Millisecond, None))
+        );
+        assert_eq!(
+            float_column(&resampled, "reading_mean"),
+            vec![Some(1.0), None]
+        );
+        let counts = resampled.record_batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(counts.values().to_vec(), vec![1, 0]);
+    }
+
+    #[test]
+    fn resample_for_request_uses_its_granularity() {
+        let ts_data = create_timeseries_data(
+            vec![Some(0), Some(30_000), Some(60_000), Some(150_000)],
+            vec![1, 2, 3, 4],
+        );
+        let mut request = JobRequest {
+            id: "1".to_string(),
+            request_id: "abc".to_string(),
+            author: "test author".to_string(),
+            name: "test job".to_string(),
+            description: "test desc".to_string(),
+            analysis_types: vec!["Exploratory Data Analysis".to_string()],
+            timestamp: 0,
+            status: crate::models::status::Status::Pending,
+            sources: vec!["dataset1".to_string()],
+            range_start: 0,
+            range_end: 150_000,
+            granularity: 60_000,
+        };
+
+        let resampled = ts_data
+            .resample_for_request(&request, &[Aggregation::Sum])
+            .unwrap();
+        assert_eq!(
+            float_column(&resampled, "value_sum"),
+            vec![Some(3.0), Some(3.0), Some(4.0)]
+        );
+
+        for granularity in [0, -60_000] {
+            request.granularity = granularity;
+            let err = ts_data
+                .resample_for_request(&request, &[Aggregation::Sum])
+                .unwrap_err();
+            assert!(err.to_string().contains("positive"), "{err}");
+        }
+    }
+
+    /// A `value` column of 1..=5 with the times 1 to 5 seconds in a `time` column built by `times`.
+    fn timed_data(time_type: DataType, times: ArrayRef) -> TimeSeriesData {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", time_type, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let values = Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5])) as ArrayRef;
+        let batch = RecordBatch::try_new(schema.clone(), vec![times, values]).unwrap();
+        TimeSeriesData {
+            schema,
+            record_batches: vec![batch],
+            time_column: None,
+        }
+    }
+
+    fn values(data: &TimeSeriesData) -> Vec<i64> {
+        let batch = &data.record_batches[0];
+        let index = batch.schema().index_of("value").unwrap();
+        batch
+            .column(index)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .values()
+            .to_vec()
+    }
+
+    #[test]
+    fn filters_work_for_every_timestamp_unit_and_timezone() {
+        let seconds: Vec<i64> = (1..=5).collect();
+        let scaled = |factor: i64| seconds.iter().map(|s| s * factor).collect::<Vec<_>>();
+        let cases: Vec<(DataType, ArrayRef)> = vec![
+            (
+                DataType::Timestamp(TimeUnit::Second, None),
+                Arc::new(TimestampSecondArray::from(scaled(1))),
+            ),
+            (
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                Arc::new(TimestampMillisecondArray::from(scaled(1_000)).with_timezone("UTC")),
+            ),
+            (
+                DataType::Timestamp(TimeUnit::Microsecond, Some("+02:00".into())),
+                Arc::new(
+                    TimestampMicrosecondArray::from(scaled(1_000_000)).with_timezone("+02:00"),
+                ),
+            ),
+            (
+                DataType::Timestamp(TimeUnit::Nanosecond, Some("America/New_York".into())),
+                Arc::new(
+                    TimestampNanosecondArray::from(scaled(1_000_000_000))
+                        .with_timezone("America/New_York"),
+                ),
+            ),
+            (DataType::Date64, Arc::new(Date64Array::from(scaled(1_000)))),
+        ];
+
+        for (time_type, times) in cases {
+            let ts_data = timed_data(time_type.clone(), times);
+            let in_range = ts_data.filter_by_time_range(2_000, 4_000).unwrap();
+            assert_eq!(values(&in_range), vec![2, 3, 4], "{time_type}");
+            let on_grid = ts_data.filter_by_granularity(2_000).unwrap();
+            assert_eq!(values(&on_grid), vec![2, 4], "{time_type}");
+        }
+
+        let day = 86_400_000;
+        let dates = timed_data(
+            DataType::Date32,
+            Arc::new(Date32Array::from(vec![1, 2, 3, 4, 5])),
+        );
+        let in_range = dates.filter_by_time_range(2 * day, 4 * day).unwrap();
+        assert_eq!(values(&in_range), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn time_column_is_named_or_read_from_metadata() {
+        let schema = Schema::new(vec![
+            Field::new(
+                "created",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new(
+                "measured",
+                DataType::Timestamp(TimeUnit::Second, None),
+                false,
+            ),
+            Field::new("value", DataType::Int64, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMillisecondArray::from(vec![0, 0, 0])),
+            Arc::new(TimestampSecondArray::from(vec![1, 2, 3])),
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+        ];
+        let batch = RecordBatch::try_new(Arc::new(schema.clone()), columns.clone()).unwrap();
+        let detected = TimeSeriesData {
+            schema: Arc::new(schema.clone()),
+            record_batches: vec![batch],
+            time_column: None,
+        };
+        assert_eq!(detected.time_column().unwrap().1.name(), "created");
+
+        let named = detected.clone().with_time_column("measured").unwrap();
+        assert_eq!(named.time_column().unwrap().0, 1);
+        let filtered = named.filter_by_time_range(2_000, 3_000).unwrap();
+        assert_eq!(values(&filtered), vec![2, 3]);
+        assert_eq!(filtered.time_column().unwrap().1.name(), "measured");
+
+        let schema = Arc::new(
+            schema.with_metadata([(TIME_COLUMN_KEY.to_string(), "measured".to_string())].into()),
+        );
+        let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+        let from_metadata = TimeSeriesData {
+            schema,
+            record_batches: vec![batch],
+            time_column: None,
+        };
+        let filtered = from_metadata.filter_by_time_range(0, 1_000).unwrap();
+        assert_eq!(values(&filtered), vec![1]);
+    }
+
+    #[test]
+    fn bad_time_columns_are_errors() {
+        let ts_data = create_timeseries_data(vec![Some(1_000)], vec![1]);
+        let err = ts_data.clone().with_time_column("value").unwrap_err();
+        assert!(err.to_string().contains("cannot be used"), "{err}");
+        let err = ts_data.clone().with_time_column("missing").unwrap_err();
+        assert!(err.to_string().contains("not found"), "{err}");
+        assert!(ts_data.filter_by_granularity(0).is_err());
+
+        let clock = TimeSeriesData {
+            schema: Arc::new(Schema::new(vec![Field::new(
+                "clock",
+                DataType::Time32(TimeUnit::Second),
+                false,
+            )])),
+            record_batches: vec![],
+            time_column: None,
+        };
+        let err = clock.filter_by_granularity(1_000).unwrap_err();
+        assert_eq!(err.to_string(), "Temporal column not found");
+    }
+
+    /// Splits `text` into chunks of `size` bytes, like a body arriving over the network.
+    fn chunked(text: &[u8], size: usize) -> impl Stream<Item = Result<Bytes>> + Unpin + Send {
+        let chunks: Vec<Result<Bytes>> = text
+            .chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        stream::iter(chunks)
+    }
+
+    #[tokio::test]
+    async fn parquet_stream_reads_the_selected_columns() -> Result<()> {
+        let timestamps = (0..2_000).map(|i| Some(i * 1_000)).collect();
+        let ts_data = create_timeseries_data(timestamps, (0..2_000).collect());
+        let outfile = NamedTempFile::new()?;
+        ts_data.to_parquet(outfile.path().to_str().unwrap())?;
+
+        let file = tokio::fs::File::open(outfile.path()).await?;
+        let stream = parquet_stream(file, Some(&["value"]), None, None).await?;
+        assert_eq!(stream.schema().fields().len(), 1);
+        let read = stream.collect().await?;
+        assert_eq!(read.field_names(), vec!["value"]);
+        let rows: usize = read.record_batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 2_000);
+
+        let file = tokio::fs::File::open(outfile.path()).await?;
+        assert!(parquet_stream(file, Some(&["missing"]), None, None)
+            .await
+            .is_err());
+
+        let file = tokio::fs::File::open(outfile.path()).await?;
+        let read = parquet_stream(file, None, Some((1_500_000, 1_502_000)), None)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(values(&read), vec![1_500, 1_501, 1_502]);
+        Ok(())
+    }
+
+    /// Writes 2000 rows a second apart in row groups of 500 rows and pages of 100.
+    fn write_paged_parquet() -> NamedTempFile {
+        let timestamps = (0..2_000).map(|i| Some(i * 1_000)).collect();
+        let ts_data = create_timeseries_data(timestamps, (0..2_000).collect());
+        let outfile = NamedTempFile::new().unwrap();
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(500)
+            .set_data_page_row_count_limit(100)
+            .set_write_batch_size(100)
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            outfile.reopen().unwrap(),
+            ts_data.schema.clone(),
+            Some(props),
+        )
+        .unwrap();
+        for batch in &ts_data.record_batches {
+            writer.write(batch).unwrap();
+        }
+        writer.close().unwrap();
+        outfile
+    }
+
+    #[test]
+    fn from_parquet_filtered_skips_what_is_outside_the_range() -> Result<()> {
+        let outfile = write_paged_parquet();
+        let infile = outfile.path().to_str().unwrap();
+
+        let read = TimeSeriesData::from_parquet_filtered(
+            infile,
+            Some(&["timestamp", "value"]),
+            Some((650_000, 849_500)),
+            None,
+        )?;
+        assert_eq!(values(&read), (650..850).collect::<Vec<_>>());
+
+        let options = ArrowReaderOptions::new().with_page_index(true);
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new_with_options(File::open(infile)?, options)?;
+        let metadata = builder.metadata();
+        let scale = millis_scale(metadata.file_metadata().schema_descr().column(0).as_ref());
+        assert_eq!(scale, Some((1, 1)));
+        let row_groups = prune_row_groups(metadata, 0, scale, 650_000, 849_500);
+        assert_eq!(row_groups, vec![1]);
+        let selection = select_pages(metadata, &row_groups, 0, scale, 650_000, 849_500).unwrap();
+        assert_eq!(selection.row_count(), 300); // the pages starting at rows 600, 700 and 800
+        assert!(prune_row_groups(metadata, 0, scale, 5_000_000, 6_000_000).is_empty());
+
+        let values_only = TimeSeriesData::from_parquet_filtered(
+            infile,
+            Some(&["value"]),
+            Some((0, 2_000)),
+            None,
+        )?;
+        assert_eq!(values_only.field_names(), vec!["value"]);
+        assert_eq!(values(&values_only), vec![0, 1, 2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parquet_reads_filter_on_the_named_time_column() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "created",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new(
+                "measured",
+                DataType::Timestamp(TimeUnit::Second, None),
+                false,
+            ),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMillisecondArray::from(vec![0, 0, 0])),
+            Arc::new(TimestampSecondArray::from(vec![1, 2, 3])),
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+        ];
+        let ts_data = TimeSeriesData {
+            schema: schema.clone(),
+            record_batches: vec![RecordBatch::try_new(schema, columns)?],
+            time_column: None,
+        };
+        let outfile = NamedTempFile::new()?;
+        let infile = outfile.path().to_str().unwrap();
+        ts_data.to_parquet(infile)?;
+
+        let read = TimeSeriesData::from_parquet_filtered(
+            infile,
+            None,
+            Some((2_000, 3_000)),
+            Some("measured"),
+        )?;
+        assert_eq!(values(&read), vec![2, 3]);
+        assert_eq!(read.time_column()?.1.name(), "measured");
+
+        let file = tokio::fs::File::open(outfile.path()).await?;
+        let streamed = parquet_stream(file, None, Some((2_000, 3_000)), Some("measured"))
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(values(&streamed), vec![2, 3]);
+        assert_eq!(streamed.time_column()?.1.name(), "measured");
+
+        // without a name the first timestamp column is picked, and no row is in range there
+        let detected =
+            TimeSeriesData::from_parquet_filtered(infile, None, Some((2_000, 3_000)), None)?;
+        let rows: usize = detected.record_batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 0);
+        assert_eq!(detected.time_column()?.1.name(), "created");
+
+        // filtering on a column that is not read leaves it out, so the data finds its own
+        let values_only = TimeSeriesData::from_parquet_filtered(
+            infile,
+            Some(&["created", "value"]),
+            Some((2_000, 3_000)),
+            Some("measured"),
+        )?;
+        assert_eq!(values(&values_only), vec![2, 3]);
+        assert_eq!(values_only.time_column()?.1.name(), "created");
+
+        let file = tokio::fs::File::open(outfile.path()).await?;
+        let streamed = parquet_stream(
+            file,
+            Some(&["value"]),
+            Some((2_000, 3_000)),
+            Some("measured"),
+        )
+        .await?
+        .collect()
+        .await?;
+        assert_eq!(streamed.field_names(), vec!["value"]);
+        assert_eq!(values(&streamed), vec![2, 3]);
+        let err = streamed.time_column().unwrap_err();
+        assert!(
+            err.to_string().contains("Temporal column not found"),
+            "{err}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn object_formats_come_from_the_extension_under_gz() {
+        let text = |format, gzipped| Ok(ObjectFormat::Text { format, gzipped });
+        let format = |uri| object_format(uri).map_err(|e| e.to_string());
+        assert_eq!(format("s3://b/a.b/data.parquet"), Ok(ObjectFormat::Parquet));
+        assert_eq!(format("s3://b/data.csv.gz"), text(TextFormat::Csv, true));
+        assert_eq!(format("s3://b/data.jsonl"), text(TextFormat::Ndjson, false));
+        assert_eq!(
+            format("s3://b/abc/20240101_00000_x.gz"),
+            text(TextFormat::Ndjson, true)
+        );
+        assert_eq!(format("s3://b/data.json.gz"), Ok(ObjectFormat::Json));
+        assert!(is_gzipped("s3://b/abc/20240101_00000_x.gz"));
+        assert!(!is_gzipped("s3://b.gz/data.csv"));
+        assert_eq!(
+            format("s3://b/data.parquet.gz"),
+            Err("Cannot stream gzipped parquet s3://b/data.parquet.gz".to_string())
+        );
+        assert_eq!(
+            format("s3://my.bucket/data"),
+            Err("Cannot tell the format of s3://my.bucket/data from its extension".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn gzipped_text_streams_are_decoded() -> Result<()> {
+        let text = "time,value\n2024-01-01T00:00:00,1\n2024-01-01T00:00:01,2\n";
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(text.as_bytes())?;
+        let gzipped = gzipped.finish()?;
+
+        let gunzipped: Vec<Bytes> = gunzip_stream(chunked(&gzipped, 3)).try_collect().await?;
+        assert_eq!(gunzipped.concat(), text.as_bytes());
+
+        let read = text_stream(gunzip_stream(chunked(&gzipped, 3)), TextFormat::Csv)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(read.field_names(), vec!["time", "value"]);
+        let rows: usize = read.record_batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn documents_are_gunzipped_as_they_arrive() -> Result<()> {
+        let text = "{\"value\":1}\n{\"value\":2}\n";
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(text.as_bytes())?;
+        let gzipped = gzipped.finish()?;
+
+        assert_eq!(read_document(chunked(&gzipped, 1)).await?, text.as_bytes());
+        assert_eq!(read_document(chunked(&gzipped, 7)).await?, text.as_bytes());
+        assert_eq!(
+            read_document(chunked(text.as_bytes(), 5)).await?,
+            text.as_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ndjson_batches_span_documents() -> Result<()> {
+        let document = |values: std::ops::Range<i64>| {
+            values
+                .map(|value| format!("{{\"value\":{value}}}\n"))
+                .collect::<String>()
+                .into_bytes()
+        };
+        let data = from_ndjson_documents(vec![document(0..300), document(300..1_100)])?;
+
+        let sizes: Vec<usize> = data.record_batches.iter().map(|b| b.num_rows()).collect();
+        assert_eq!(sizes, vec![BATCH_SIZE, BATCH_SIZE, 1_100 - 2 * BATCH_SIZE]);
+        let last = data.record_batches[2].column(0);
+        assert_eq!(
+            last.as_primitive::<arrow::datatypes::Int64Type>().value(0),
+            2 * BATCH_SIZE as i64
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_ndjson_reads_ctas_part_files_as_one_dataset() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("part-0"),
+            "{\"time\":\"2024-01-01 00:00:00.000\",\"name\":\"a\",\"sensor\":{\"id\":1,\"position\":{\"lat\":1.5}}}\n\
+             {\"time\":\"2024-01-01 00:00:01.000\",\"name\":\"b\"}\n",
+        )?;
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(
+            b"{\"time\":\"2024-01-01T00:00:02Z\",\"name\":\"c\",\"sensor\":{\"id\":3},\"extra\":true}\n",
+        )?;
+        fs::write(dir.path().join("part-1.gz"), gzipped.finish()?)?;
+        fs::write(dir.path().join("_SUCCESS"), "")?;
+
+        let data = TimeSeriesData::from_ndjson(dir.path().to_str().unwrap())?;
+        let mut names = data.field_names();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["extra", "name", "sensor.id", "sensor.position.lat", "time"]
+        );
+        assert_eq!(
+            data.field_type("time"),
+            Some(DataType::Timestamp(TimeUnit::Millisecond, None))
+        );
+        assert_eq!(data.field_type("name"), Some(DataType::Utf8));
+
+        let batch = &data.record_batches[0];
+        assert_eq!(batch.num_rows(), 3);
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let times = column("time");
+        assert_eq!(
+            times
+                .as_primitive::<TimestampMillisecondType>()
+                .values()
+                .to_vec(),
+            vec![1_704_067_200_000, 1_704_067_201_000, 1_704_067_202_000]
+        );
+        let ids = column("sensor.id");
+        assert_eq!(
+            ids.as_primitive::<arrow::datatypes::Int64Type>()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![Some(1), None, Some(3)]
+        );
+        let lats = column("sensor.position.lat");
+        assert_eq!(
+            lats.as_primitive::<Float64Type>()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![Some(1.5), None, None]
+        );
+
+        let filtered = data.filter_by_time_range(1_704_067_201_000, 1_704_067_202_000)?;
+        assert_eq!(filtered.record_batches[0].num_rows(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn from_json_reads_arrays_of_objects() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            "[{{\"time\": \"2024-01-01T00:00:00\", \"value\": 1}},\n {{\"time\": \"2024-01-01T00:00:01\", \"value\": 2.5}}]"
+        )?;
+
+        let data = TimeSeriesData::from_json(file.path().to_str().unwrap())?;
+        assert_eq!(data.field_names(), vec!["time", "value"]);
+        assert_eq!(data.field_type("value"), Some(DataType::Float64));
+        assert_eq!(data.time_column()?.1.name(), "time");
+        assert_eq!(data.record_batches[0].num_rows(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn statistics_bounds_round_outwards_to_milliseconds() {
+        // microseconds -1_500..=2_500 hold the milliseconds -2..=3
+        assert!(may_overlap(-1_500, 2_500, (1, 1_000), 3, 10));
+        assert!(may_overlap(-1_500, 2_500, (1, 1_000), -10, -2));
+        assert!(!may_overlap(-1_500, 2_500, (1, 1_000), 4, 10));
+        // days 1..=2 start at 86_400_000 and 172_800_000
+        assert!(may_overlap(1, 2, (86_400_000, 1), 172_800_000, 172_800_000));
+        assert!(!may_overlap(1, 2, (86_400_000, 1), 0, 86_399_999));
+    }
+
+    #[tokio::test]
+    async fn text_streams_decode_records_split_across_chunks() -> Result<()> {
+        let mut csv_text = "time,value\n".to_string();
+        for i in 0..1_200 {
+            csv_text.push_str(&format!("2024-01-01T00:00:{:02},{}.5\n", i % 60, i));
+        }
+        let mut stream = text_stream(chunked(csv_text.as_bytes(), 7), TextFormat::Csv).await?;
+        assert_eq!(
+            stream.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Second, None)
+        );
+        let mut sizes = Vec::new();
+        while let Some(batch) = stream.try_next().await? {
+            sizes.push(batch.num_rows());
+        }
+        assert_eq!(sizes, vec![BATCH_SIZE, BATCH_SIZE, 1_200 - 2 * BATCH_SIZE]);
+
+        let ndjson_text = "{\"time\":\"2024-01-01T00:00:00\",\"value\":1}\n\
+                           {\"time\":\"2024-01-01T00:00:01\",\"value\":2,\"note\":\"late\"}";
+        let read = text_stream(chunked(ndjson_text.as_bytes(), 5), TextFormat::Ndjson)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(read.field_names(), vec!["time", "value", "note"]);
+        assert_eq!(read.record_batches.len(), 1);
+        assert_eq!(read.record_batches[0].num_rows(), 2);
+        Ok(())
+    }
 }
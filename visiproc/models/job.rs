@@ -1,71 +1,241 @@
-use crate::models::{job_request::JobRequest, status::Status};
-use aws_sdk_dynamodb::types::AttributeValue;
-use eyre::Result;
+use crate::{
+    models::{
+        artifact::Artifact,
+        job_request::JobRequest,
+        job_type::{deserialize_job_type, serialize_job_type, JobType},
+        status::{deserialize_status, serialize_status, InvalidTransition, Status},
+    },
+    utils::worker_id,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Job {
-    pub job_id: String,              // db key
-    pub request_id: String,          // points to the originating JobRequest
+    #[serde(rename = "jobID")]
+    pub job_id: String, // db key
+    #[serde(rename = "requestID")]
+    pub request_id: String, // points to the originating JobRequest
+    #[serde(
+        rename = "jobType",
+        serialize_with = "serialize_job_type",
+        deserialize_with = "deserialize_job_type"
+    )]
+    pub job_type: JobType, // the analysis the job runs
+    #[serde(rename = "responseID")]
     pub current_response_id: String, // points to the latest JobResponse
-    pub status: Status,              // job status
-    pub last_updated: i64,           // timestamp
-    pub s3_path: String,             // s3 path
+    #[serde(
+        rename = "jobStatus",
+        serialize_with = "serialize_status",
+        deserialize_with = "deserialize_status"
+    )]
+    pub status: Status, // job status
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: i64, // timestamp
+    #[serde(rename = "inputPath")]
+    pub s3_path: String, // s3 path
+    #[serde(rename = "errorMessage", default)]
+    pub error: Option<String>, // failure message when status is Failed
+    #[serde(default)]
+    pub attempts: u32, // number of times the job has been run
+    #[serde(rename = "workerID")]
+    pub worker_id: String, // visiproc instance that owns the job
+    #[serde(rename = "startTimestamp", default)]
+    pub start_timestamp: Option<i64>, // when processing first started
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<Artifact>, // uploaded results
+    #[serde(rename = "statusHistory", default)]
+    pub history: Vec<StatusChange>, // every status change, oldest first
 }
 
-pub fn create_job_from_request(job_request: &JobRequest) -> Job {
+/// A status change of a job, recorded on the job as its audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusChange {
+    #[serde(
+        serialize_with = "serialize_status",
+        deserialize_with = "deserialize_status"
+    )]
+    pub from: Status,
+    #[serde(
+        serialize_with = "serialize_status",
+        deserialize_with = "deserialize_status"
+    )]
+    pub to: Status,
+    #[serde(default)]
+    pub reason: Option<String>, // why, for failures, retries and re-queues
+    #[serde(rename = "workerID")]
+    pub worker_id: String, // visiproc instance that made the change
+    pub timestamp: i64,
+}
+
+impl Job {
+    /// Moves the job to `to` if `Status::transition` allows it, recording the change.
+    pub fn transition(
+        &mut self,
+        to: Status,
+        reason: Option<&str>,
+    ) -> Result<(), InvalidTransition> {
+        let from = self.status.clone();
+        self.status = from.transition(to.clone())?;
+        self.history.push(StatusChange {
+            from,
+            to,
+            reason: reason.map(str::to_string),
+            worker_id: worker_id().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+        Ok(())
+    }
+}
+
+/// A PENDING job running the `job_type` analysis of `job_request`, reading its input from and
+/// writing its results to a prefix of `results_bucket` named after the request.
+pub fn create_job_from_request(
+    job_request: &JobRequest,
+    job_type: JobType,
+    results_bucket: &str,
+) -> Job {
     Job {
-        job_id: job_request.request_id.clone(),
+        job_id: format!("{}-{}", job_request.request_id, job_type.slug()),
         request_id: job_request.request_id.clone(),
+        job_type,
         current_response_id: String::new(), // initially empty, updated as job progresses
         status: Status::Pending,
         last_updated: chrono::Utc::now().timestamp(),
-        s3_path: format!("s3://metadata/{}/", job_request.request_id.clone()),
+        s3_path: format!("s3://{}/{}/", results_bucket, job_request.request_id),
+        error: None,
+        attempts: 0,
+        worker_id: worker_id().to_string(),
+        start_timestamp: None,
+        artifacts: Vec::new(),
+        history: Vec::new(),
     }
 }
 
-pub fn _convert_item_to_job(item: &HashMap<String, AttributeValue>) -> Result<Job> {
-    let job = Job {
-        job_id: item
-            .get("jobID")
-            .ok_or_else(|| eyre::Error::msg("Missing job id"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid job id"))?
-            .to_owned(),
-        request_id: item
-            .get("requestID")
-            .ok_or_else(|| eyre::Error::msg("Missing requestID"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid requestID"))?
-            .to_owned(),
-        current_response_id: item
-            .get("responseID")
-            .ok_or_else(|| eyre::Error::msg("Missing requestID"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid requestID"))?
-            .to_owned(),
-        status: item
-            .get("jobStatus")
-            .ok_or_else(|| eyre::Error::msg("Missing jobStatus"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid jobStatus"))?
-            .parse::<Status>()
-            .map_err(|_| eyre::Error::msg("Failed to parse jobStatus into a Status enum"))?,
-        last_updated: item
-            .get("lastUpdated")
-            .ok_or_else(|| eyre::Error::msg("Missing lastUpdated"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid lastUpdated"))?
-            .parse::<i64>()
-            .map_err(|_| eyre::Error::msg("Invalid timestamp format"))?,
-        s3_path: item
-            .get("inputPath")
-            .ok_or_else(|| eyre::Error::msg("Missing inputPath"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid inputPath"))?
-            .to_owned(),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::item::{from_item, to_item};
+    use aws_sdk_dynamodb::types::AttributeValue;
 
-    Ok(job)
+    fn job() -> Job {
+        Job {
+            job_id: "abc-eda".to_string(),
+            request_id: "abc".to_string(),
+            job_type: JobType::Eda,
+            current_response_id: String::new(),
+            status: Status::Processing,
+            last_updated: 1700000000,
+            s3_path: "s3://metadata/abc/".to_string(),
+            error: None,
+            attempts: 1,
+            worker_id: "worker-1".to_string(),
+            start_timestamp: Some(1700000000),
+            artifacts: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn jobs_round_trip() {
+        let item = to_item(&job()).unwrap();
+        assert_eq!(
+            item["jobStatus"],
+            AttributeValue::S("PROCESSING".to_string())
+        );
+        assert_eq!(
+            item["jobType"],
+            AttributeValue::S("Exploratory Data Analysis".to_string())
+        );
+        assert_eq!(
+            item["startTimestamp"],
+            AttributeValue::N("1700000000".to_string())
+        );
+        assert!(!item.contains_key("errorMessage"));
+        assert!(!item.contains_key("artifacts"));
+
+        let mut failed = Job {
+            error: Some("timeout".to_string()),
+            artifacts: vec![Artifact {
+                kind: "log".to_string(),
+                uri: "s3://metadata/abc/abc-eda.log".to_string(),
+                mime_type: "text/plain".to_string(),
+                description: "script output".to_string(),
+            }],
+            ..job()
+        };
+        failed.transition(Status::Failed, Some("timeout")).unwrap();
+        let item = to_item(&failed).unwrap();
+        let read: Job = from_item(&item).unwrap();
+        assert_eq!(read.status, Status::Failed);
+        assert_eq!(read.history, failed.history);
+        assert_eq!(read.error.as_deref(), Some("timeout"));
+        assert_eq!(read.artifacts, failed.artifacts);
+        assert_eq!(to_item(&read).unwrap(), item);
+    }
+
+    #[test]
+    fn optional_attributes_default_when_missing() {
+        let mut item = to_item(&job()).unwrap();
+        item.remove("attempts");
+        item.remove("startTimestamp");
+        let read: Job = from_item(&item).unwrap();
+        assert_eq!(read.attempts, 0);
+        assert_eq!(read.start_timestamp, None);
+        assert!(read.artifacts.is_empty());
+    }
+
+    #[test]
+    fn invalid_artifacts_are_named() {
+        let mut item = to_item(&job()).unwrap();
+        item.insert(
+            "artifacts".to_string(),
+            AttributeValue::L(vec![AttributeValue::M(
+                [("kind".to_string(), AttributeValue::S("log".to_string()))].into(),
+            )]),
+        );
+        let err = from_item::<Job>(&item).unwrap_err();
+        assert_eq!(err.path(), "artifacts[0]");
+        assert!(err.to_string().contains("missing field"), "{err}");
+    }
+
+    #[test]
+    fn transitions_are_validated_and_recorded() {
+        let mut job = job();
+        job.transition(Status::Retrying, Some("exit code 1"))
+            .unwrap();
+        job.transition(Status::Processing, None).unwrap();
+        job.transition(Status::Completed, None).unwrap();
+
+        let err = job.transition(Status::Failed, Some("late")).unwrap_err();
+        assert_eq!(err.to_string(), "cannot move from COMPLETE to FAILED");
+        assert_eq!(job.status, Status::Completed);
+
+        let moves: Vec<_> = job
+            .history
+            .iter()
+            .map(|change| {
+                (
+                    change.from.clone(),
+                    change.to.clone(),
+                    change.reason.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            moves,
+            vec![
+                (
+                    Status::Processing,
+                    Status::Retrying,
+                    Some("exit code 1".to_string())
+                ),
+                (Status::Retrying, Status::Processing, None),
+                (Status::Processing, Status::Completed, None),
+            ]
+        );
+        assert!(job
+            .history
+            .iter()
+            .all(|change| change.worker_id == worker_id()));
+    }
 }
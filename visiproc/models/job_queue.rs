@@ -1,83 +1,419 @@
-use super::job::Job;
-use crate::analysis::analysis_jobs::AnalysisJob;
+use super::{job::Job, job_type::JobType, status::Status};
+use crate::{
+    analysis::{
+        analysis_jobs::{AnalysisJob, ScriptLog},
+        errors::JobError,
+        retry::RetryPolicy,
+    },
+    store::{BlobStore, JobStore},
+};
 use eyre::Result;
-use log::debug;
+use log::{debug, error, warn};
 use std::{
+    collections::{HashMap, HashSet},
     fmt,
     sync::{Arc, Mutex},
-    vec::IntoIter,
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+    time::sleep,
 };
 
-pub struct JobQueue {
-    jobs: Vec<(Box<dyn AnalysisJob>, Arc<Mutex<Job>>)>,
+const DEFAULT_MAX_WORKERS: usize = 4;
+const DEFAULT_EDA_LIMIT: usize = 2;
+const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Concurrency limits applied by `JobQueue::run`.
+#[derive(Debug, Clone)]
+pub struct QueueLimits {
+    pub max_workers: usize, // jobs running at once, across all types
+    pub type_limits: HashMap<JobType, usize>, // jobs running at once, per type
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits {
+            max_workers: DEFAULT_MAX_WORKERS,
+            type_limits: HashMap::from([(JobType::Eda, DEFAULT_EDA_LIMIT)]),
+        }
+    }
 }
 
-pub struct JobQueueIterator {
-    iter: IntoIter<Arc<Mutex<Job>>>,
+/// Cancels the jobs of a `JobQueue`, whether they are still queued or already running.
+/// Clones share the same jobs, so a handle can be kept while the queue itself is busy running.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    jobs: Arc<Mutex<HashMap<String, watch::Sender<bool>>>>, // cancellation flag by job id
 }
 
-impl Iterator for JobQueueIterator {
-    type Item = Arc<Mutex<Job>>;
+impl CancelHandle {
+    /// Requests cancellation of the job, returning false if the queue has no such job.
+    pub fn cancel(&self, job_id: &str) -> bool {
+        match self.jobs.lock().unwrap().get(job_id) {
+            Some(cancelled) => {
+                cancelled.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn subscribe(&self, job_id: &str) -> watch::Receiver<bool> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .entry(job_id.to_string())
+            .or_insert_with(|| watch::channel(false).0)
+            .subscribe()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next()
+    fn forget(&self, job_id: &str) {
+        self.jobs.lock().unwrap().remove(job_id);
     }
 }
 
+/// Resolves once `flag` is raised, e.g. the job behind it is cancelled.
+async fn raised(flag: &mut watch::Receiver<bool>) {
+    if flag.wait_for(|raised| *raised).await.is_err() {
+        // the sender is gone, so nothing can raise the flag anymore
+        std::future::pending::<()>().await
+    }
+}
+
+/// A job's implementation along with its record.
+type QueuedJob = (Arc<dyn AnalysisJob>, Arc<Mutex<Job>>);
+
+pub struct JobQueue {
+    jobs: Vec<QueuedJob>,
+    limits: QueueLimits,
+    retry_policies: HashMap<JobType, RetryPolicy>, // overrides of `RetryPolicy::for_job_type`
+    timeouts: HashMap<JobType, Duration>,          // overrides of `DEFAULT_JOB_TIMEOUT`
+    job_store: Option<Arc<dyn JobStore>>,          // where job records are persisted
+    blobs: Arc<dyn BlobStore>,                     // where job results are uploaded
+    cancel: CancelHandle,
+    shutdown: watch::Receiver<bool>, // once true, jobs that have not started are left alone
+    running: Arc<Mutex<HashSet<usize>>>, // indices into `jobs`
+}
+
 impl JobQueue {
-    pub fn new() -> Self {
-        JobQueue { jobs: Vec::new() }
+    pub fn new(blobs: Arc<dyn BlobStore>) -> Self {
+        JobQueue {
+            jobs: Vec::new(),
+            limits: QueueLimits::default(),
+            retry_policies: HashMap::new(),
+            timeouts: HashMap::new(),
+            job_store: None,
+            blobs,
+            cancel: CancelHandle::default(),
+            shutdown: watch::channel(false).1,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: QueueLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Mirrors every status change of the queued jobs to `job_store`.
+    pub fn with_job_store(mut self, job_store: Arc<dyn JobStore>) -> Self {
+        self.job_store = Some(job_store);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, job_type: JobType, policy: RetryPolicy) -> Self {
+        self.retry_policies.insert(job_type, policy);
+        self
+    }
+
+    pub fn retry_policy(&self, job_type: JobType) -> RetryPolicy {
+        self.retry_policies
+            .get(&job_type)
+            .cloned()
+            .unwrap_or_else(|| RetryPolicy::for_job_type(job_type))
+    }
+
+    /// Limits how long a single run of a job of `job_type` may take before it is killed.
+    pub fn with_timeout(mut self, job_type: JobType, timeout: Duration) -> Self {
+        self.timeouts.insert(job_type, timeout);
+        self
+    }
+
+    pub fn timeout(&self, job_type: JobType) -> Duration {
+        self.timeouts
+            .get(&job_type)
+            .cloned()
+            .unwrap_or(DEFAULT_JOB_TIMEOUT)
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Stops `run` from starting jobs once `shutdown` turns true.
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = shutdown;
+        self
     }
 
-    pub fn add_job(&mut self, job_impl: Box<dyn AnalysisJob>, job_metadata: Arc<Mutex<Job>>) {
+    pub fn add_job(&mut self, job_impl: Arc<dyn AnalysisJob>, job_metadata: Arc<Mutex<Job>>) {
+        self.cancel.subscribe(&job_metadata.lock().unwrap().job_id);
         self.jobs.push((job_impl, job_metadata));
     }
 
-    pub fn iter(&self) -> JobQueueIterator {
-        let jobs_only = self
-            .jobs
+    /// Every job in the queue, in the order they were added.
+    pub fn jobs(&self) -> Vec<Arc<Mutex<Job>>> {
+        self.jobs
             .iter()
-            .map(|(_, job_meta)| job_meta.clone())
-            .collect::<Vec<_>>();
-        JobQueueIterator {
-            iter: jobs_only.into_iter(),
-        }
+            .map(|(_, job_metadata)| job_metadata.clone())
+            .collect()
+    }
+
+    /// Takes the jobs of the request out of the queue, returning them.
+    pub fn remove_request(&mut self, request_id: &str) -> Vec<Arc<Mutex<Job>>> {
+        let (removed, kept): (Vec<QueuedJob>, Vec<QueuedJob>) = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .partition(|(_, job_metadata)| job_metadata.lock().unwrap().request_id == request_id);
+        self.jobs = kept;
+        removed
+            .into_iter()
+            .map(|(_, job_metadata)| {
+                self.cancel.forget(&job_metadata.lock().unwrap().job_id);
+                job_metadata
+            })
+            .collect()
+    }
+
+    /// Writes the job to the job store, if one is configured, stamping `last_updated`.
+    /// A failed write is only logged.
+    pub async fn persist(&self, job_metadata: &Arc<Mutex<Job>>) {
+        persist(&self.job_store, job_metadata).await
+    }
+
+    /// Like `persist`, but returns the error of a failed write, for callers that must not go on
+    /// without the job being stored.
+    pub async fn try_persist(&self, job_metadata: &Arc<Mutex<Job>>) -> Result<()> {
+        try_persist(&self.job_store, job_metadata).await
     }
 
+    /// Runs every queued job on a pool of at most `max_workers` tokio tasks,
+    /// additionally bounded by the per-type limits. Failed runs are retried
+    /// according to the job type's `RetryPolicy`. Jobs that are not QUEUED are skipped.
+    ///
+    /// A job is RETRYING while it waits for its next attempt. A run that outlives the job type's
+    /// timeout is killed and the job FAILED with "timeout", and one whose job is cancelled through
+    /// a `CancelHandle` is killed and the job CANCELLED.
+    ///
+    /// Once the queue's shutdown signal is raised, jobs that have not started are left QUEUED, or
+    /// RETRYING, for `recover_jobs` to pick up, while the running ones are finished.
     pub async fn run(&self) -> Result<()> {
-        for (job_impl, job_metadata) in &self.jobs {
-            {
-                // Lock and update the status to Processing
-                let mut job = job_metadata.lock().unwrap();
-                if let Some(new_status) = job.status.next() {
-                    job.status = new_status;
-                    debug!("Job {} - {}", job.job_id, job.status);
-                }
-            } // lock dropped here
+        let workers = Arc::new(Semaphore::new(self.limits.max_workers.max(1)));
+        let type_permits: HashMap<JobType, Arc<Semaphore>> = self
+            .limits
+            .type_limits
+            .iter()
+            .map(|(job_type, limit)| (*job_type, Arc::new(Semaphore::new((*limit).max(1)))))
+            .collect();
 
-            // run job
-            job_impl.run(job_metadata.clone()).await?;
+        let mut tasks = JoinSet::new();
+        for (index, (job_impl, job_metadata)) in self.jobs.iter().enumerate() {
+            if job_metadata.lock().unwrap().status != Status::Queued {
+                continue;
+            }
 
-            // update job status to complete
-            let mut job = job_metadata.lock().unwrap();
-            if let Some(new_status) = job.status.next() {
-                job.status = new_status;
-                debug!("Job {} - {}", job.job_id, job.status);
-            } // lock dropped here
+            let job_impl = job_impl.clone();
+            let job_metadata = job_metadata.clone();
+            let workers = workers.clone();
+            let type_permit = type_permits.get(&job_impl.job_type()).cloned();
+            let retry_policy = self.retry_policy(job_impl.job_type());
+            let timeout = self.timeout(job_impl.job_type());
+            let mut cancel = self.cancel.subscribe(&job_metadata.lock().unwrap().job_id);
+            let mut shutdown = self.shutdown.clone();
+            let job_store = self.job_store.clone();
+            let blobs = self.blobs.clone();
+            let running = self.running.clone();
+
+            tasks.spawn(async move {
+                let result = loop {
+                    let permits = async {
+                        // take the per-type permit first so a saturated type doesn't sit on a worker
+                        let type_permit = match &type_permit {
+                            Some(permits) => Some(permits.clone().acquire_owned().await?),
+                            None => None,
+                        };
+                        let worker_permit = workers.clone().acquire_owned().await?;
+                        Ok::<_, eyre::Report>((type_permit, worker_permit))
+                    };
+                    // a job still waiting for a permit is dropped as soon as it is cancelled
+                    let (_type_permit, _worker_permit) = tokio::select! {
+                        permits = permits => permits?,
+                        _ = raised(&mut cancel) => break Err(JobError::Cancelled.into()),
+                        _ = raised(&mut shutdown) => return Ok(()),
+                    };
+                    if *cancel.borrow() {
+                        break Err(JobError::Cancelled.into());
+                    }
+                    if *shutdown.borrow() {
+                        return Ok(());
+                    }
+
+                    {
+                        // Lock and update the status to Processing
+                        let mut job = job_metadata.lock().unwrap();
+                        transition(&mut job, Status::Processing, None);
+                        job.start_timestamp
+                            .get_or_insert(chrono::Utc::now().timestamp());
+                        job.attempts += 1;
+                    } // lock dropped here
+                    persist(&job_store, &job_metadata).await;
+
+                    // run job
+                    running.lock().unwrap().insert(index);
+                    let log = ScriptLog::default();
+                    let mut run = job_impl.run(job_metadata.clone(), blobs.clone(), log.clone());
+                    let result = tokio::select! {
+                        result = &mut run => result,
+                        _ = sleep(timeout) => Err(JobError::Timeout.into()),
+                        _ = raised(&mut cancel) => Err(JobError::Cancelled.into()),
+                    };
+                    // dropping a run stopped on timeout or cancellation kills the script, whose
+                    // log is then ours to upload and record, as the run never got to it
+                    let stopped = log.claim();
+                    drop(run);
+                    if stopped {
+                        log.upload(blobs.as_ref(), &job_metadata).await;
+                    }
+                    running.lock().unwrap().remove(&index);
+
+                    let err = match result {
+                        Ok(()) => break Ok(()),
+                        Err(err) => err,
+                    };
+                    let (job_id, attempts) = {
+                        let job = job_metadata.lock().unwrap();
+                        (job.job_id.clone(), job.attempts)
+                    };
+                    if !retry_policy.should_retry(attempts, &err) {
+                        break Err(err);
+                    }
+
+                    // permits are released while backing off
+                    let delay = retry_policy.delay(attempts);
+                    warn!("Job {job_id} attempt {attempts} failed, retrying in {delay:?}: {err:#}");
+                    drop((_type_permit, _worker_permit));
+                    {
+                        let mut job = job_metadata.lock().unwrap();
+                        transition(&mut job, Status::Retrying, Some(&format!("{err:#}")));
+                    } // lock dropped here
+                    persist(&job_store, &job_metadata).await;
+                    tokio::select! {
+                        _ = sleep(delay) => {}
+                        _ = raised(&mut cancel) => break Err(JobError::Cancelled.into()),
+                        _ = raised(&mut shutdown) => return Ok(()),
+                    }
+                };
+
+                {
+                    let mut job = job_metadata.lock().unwrap();
+                    match result {
+                        Ok(()) => {
+                            transition(&mut job, Status::Completed, None);
+                        }
+                        // a failed job is recorded and the rest of the queue keeps going
+                        Err(err) => {
+                            error!(
+                                "Job {} ({}) failed after {} attempt(s): {err:#}",
+                                job.job_id,
+                                job_impl.type_name(),
+                                job.attempts
+                            );
+                            let status = match err.downcast_ref() {
+                                Some(JobError::Cancelled) => Status::Cancelled,
+                                _ => Status::Failed,
+                            };
+                            let reason = format!("{err:#}");
+                            if transition(&mut job, status, Some(&reason)) {
+                                job.error = Some(reason);
+                            }
+                        }
+                    }
+                } // lock dropped here
+                persist(&job_store, &job_metadata).await;
+
+                Ok::<(), eyre::Report>(())
+            });
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            result??;
         }
         Ok(())
     }
 }
 
+/// Moves the job to `to`, logging instead of failing when the move is not allowed, e.g. when
+/// the job was changed outside of the queue. Returns whether the job moved.
+fn transition(job: &mut Job, to: Status, reason: Option<&str>) -> bool {
+    match job.transition(to, reason) {
+        Ok(()) => {
+            debug!("Job {} - {}", job.job_id, job.status);
+            true
+        }
+        Err(err) => {
+            warn!("Job {}: {}", job.job_id, err);
+            false
+        }
+    }
+}
+
+async fn persist(job_store: &Option<Arc<dyn JobStore>>, job_metadata: &Arc<Mutex<Job>>) {
+    // in-memory state stays authoritative for this run, so a failed write is logged rather than failing the job
+    if let Err(err) = try_persist(job_store, job_metadata).await {
+        let job_id = job_metadata.lock().unwrap().job_id.clone();
+        error!("Failed to persist job {}: {err:#}", job_id);
+    }
+}
+
+async fn try_persist(
+    job_store: &Option<Arc<dyn JobStore>>,
+    job_metadata: &Arc<Mutex<Job>>,
+) -> Result<()> {
+    let Some(job_store) = job_store else {
+        return Ok(());
+    };
+
+    let job = {
+        let mut job = job_metadata.lock().unwrap();
+        job.last_updated = chrono::Utc::now().timestamp();
+        job.clone()
+    }; // lock dropped here, before the write
+
+    job_store.put_job(&job).await
+}
+
 impl fmt::Display for JobQueue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "JobQueue contains {} jobs:", self.jobs.len())?;
+        let running = self.running.lock().unwrap().clone();
+        writeln!(
+            f,
+            "JobQueue contains {} jobs, {} running (max {} workers):",
+            self.jobs.len(),
+            running.len(),
+            self.limits.max_workers
+        )?;
         for (index, (job_impl, job_metadata)) in self.jobs.iter().enumerate() {
             let job = job_metadata.lock().unwrap(); // Lock to safely access job details
             writeln!(f, "  Job {}:", index + 1)?;
             writeln!(f, "    Type: {}", job_impl.type_name())?;
             writeln!(f, "    ID: {}", job.job_id)?;
             writeln!(f, "    Status: {}", job.status)?;
+            writeln!(f, "    Attempts: {}", job.attempts)?;
+            if let Some(error) = &job.error {
+                writeln!(f, "    Error: {}", error)?;
+            }
+            writeln!(f, "    Running: {}", running.contains(&index))?;
             writeln!(f, "    Request ID: {}", job.request_id)?;
             writeln!(f, "    s3 Path: {}", job.s3_path)?;
             drop(job);
@@ -85,3 +421,310 @@ impl fmt::Display for JobQueue {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::{
+            analysis_jobs::Script,
+            errors::{ErrorClass, JobError},
+        },
+        store::memory::MemoryBlobs,
+    };
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    struct SleepJob {
+        job_type: JobType,
+        failures: AtomicUsize, // runs left that fail before one succeeds
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+        script: Script,
+    }
+
+    impl AnalysisJob for SleepJob {
+        fn run(
+            &self,
+            _job: Arc<Mutex<Job>>,
+            _blobs: Arc<dyn BlobStore>,
+            log: ScriptLog,
+        ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+            Box::pin(async move {
+                log.push_line("sleeping");
+                let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                if self.failures.load(Ordering::SeqCst) > 0 {
+                    self.failures.fetch_sub(1, Ordering::SeqCst);
+                    return Err(JobError::Script {
+                        code: Some(1),
+                        stderr: "simulated failure".to_string(),
+                    }
+                    .into());
+                }
+                Ok(())
+            })
+        }
+
+        fn job_type(&self) -> JobType {
+            self.job_type
+        }
+
+        fn type_name(&self) -> &'static str {
+            "Sleep Job"
+        }
+
+        fn script(&self) -> &Script {
+            &self.script
+        }
+    }
+
+    fn queued_job(id: usize) -> Arc<Mutex<Job>> {
+        Arc::new(Mutex::new(Job {
+            job_id: id.to_string(),
+            request_id: id.to_string(),
+            job_type: JobType::SimulatedJob,
+            current_response_id: String::new(),
+            status: Status::Queued,
+            last_updated: 0,
+            s3_path: String::new(),
+            error: None,
+            attempts: 0,
+            worker_id: String::new(),
+            start_timestamp: None,
+            artifacts: Vec::new(),
+            history: Vec::new(),
+        }))
+    }
+
+    async fn peak_concurrency(limits: QueueLimits, job_type: JobType, jobs: usize) -> usize {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default())).with_limits(limits);
+
+        for id in 0..jobs {
+            let job_impl = Arc::new(SleepJob {
+                job_type,
+                failures: AtomicUsize::new(0),
+                active: active.clone(),
+                peak: peak.clone(),
+                script: Script::default(),
+            });
+            job_queue.add_job(job_impl, queued_job(id));
+        }
+
+        job_queue.run().await.unwrap();
+        for job in job_queue.jobs() {
+            assert_eq!(job.lock().unwrap().status, Status::Completed);
+        }
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn run_is_bounded_by_max_workers() {
+        let limits = QueueLimits {
+            max_workers: 3,
+            type_limits: HashMap::new(),
+        };
+        assert_eq!(peak_concurrency(limits, JobType::SimulatedJob, 8).await, 3);
+    }
+
+    #[tokio::test]
+    async fn run_is_bounded_by_type_limit() {
+        let limits = QueueLimits {
+            max_workers: 8,
+            type_limits: HashMap::from([(JobType::Eda, 2)]),
+        };
+        assert_eq!(peak_concurrency(limits, JobType::Eda, 6).await, 2);
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_recorded_and_queue_keeps_going() {
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()));
+        for (id, failures) in [0, 1, 0].into_iter().enumerate() {
+            let job_impl = Arc::new(SleepJob {
+                job_type: JobType::SimulatedJob,
+                failures: AtomicUsize::new(failures),
+                active: Arc::new(AtomicUsize::new(0)),
+                peak: Arc::new(AtomicUsize::new(0)),
+                script: Script::default(),
+            });
+            job_queue.add_job(job_impl, queued_job(id));
+        }
+
+        job_queue.run().await.unwrap();
+
+        let jobs = job_queue
+            .jobs()
+            .into_iter()
+            .map(|job| job.lock().unwrap().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(jobs[0].status, Status::Completed);
+        assert_eq!(jobs[1].status, Status::Failed);
+        assert_eq!(
+            jobs[1].error.as_deref(),
+            Some("Job analysis run failed (exit code 1): simulated failure")
+        );
+        assert_eq!(jobs[2].status, Status::Completed);
+    }
+
+    #[tokio::test]
+    async fn failed_job_is_retried_until_it_succeeds() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            retryable: HashSet::from([ErrorClass::Script]),
+            ..RetryPolicy::never()
+        };
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()))
+            .with_retry_policy(JobType::SimulatedJob, policy);
+        let job_impl = Arc::new(SleepJob {
+            job_type: JobType::SimulatedJob,
+            failures: AtomicUsize::new(2),
+            active: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+            script: Script::default(),
+        });
+        job_queue.add_job(job_impl, queued_job(0));
+
+        job_queue.run().await.unwrap();
+
+        let job = job_queue.jobs().remove(0);
+        let job = job.lock().unwrap();
+        assert_eq!(job.status, Status::Completed);
+        assert_eq!(job.attempts, 3);
+        let statuses: Vec<_> = job.history.iter().map(|change| change.to.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                Status::Processing,
+                Status::Retrying,
+                Status::Processing,
+                Status::Retrying,
+                Status::Processing,
+                Status::Completed,
+            ]
+        );
+        assert_eq!(
+            job.history[1].reason.as_deref(),
+            Some("Job analysis run failed (exit code 1): simulated failure")
+        );
+    }
+
+    fn sleep_job() -> Arc<SleepJob> {
+        Arc::new(SleepJob {
+            job_type: JobType::SimulatedJob,
+            failures: AtomicUsize::new(0),
+            active: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+            script: Script::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn job_past_its_timeout_is_failed() {
+        let blobs = Arc::new(MemoryBlobs::default());
+        let mut job_queue = JobQueue::new(blobs.clone())
+            .with_timeout(JobType::SimulatedJob, Duration::from_millis(1));
+        let job = queued_job(0);
+        job.lock().unwrap().s3_path = "s3://metadata/0/".to_string();
+        job_queue.add_job(sleep_job(), job);
+
+        job_queue.run().await.unwrap();
+
+        let job = job_queue.jobs().remove(0);
+        let job = job.lock().unwrap();
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.error.as_deref(), Some("timeout"));
+        // the killed run's log is uploaded and recorded like that of a finished run
+        let log = blobs.get("s3://metadata/0/0.log").unwrap();
+        assert_eq!(log.body, b"sleeping\n");
+        assert_eq!(job.artifacts.len(), 1);
+        assert_eq!(job.artifacts[0].uri, "s3://metadata/0/0.log");
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_are_cancelled() {
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()));
+        job_queue.add_job(sleep_job(), queued_job(0));
+        job_queue.add_job(sleep_job(), queued_job(1));
+
+        let cancel = job_queue.cancel_handle();
+        assert!(!cancel.cancel("unknown"));
+        assert!(cancel.cancel("1")); // before it ever runs
+
+        let (result, _) = tokio::join!(job_queue.run(), async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            assert!(cancel.cancel("0")); // while it runs
+        });
+        result.unwrap();
+
+        for job in job_queue.jobs() {
+            let job = job.lock().unwrap();
+            assert_eq!(job.status, Status::Cancelled);
+            assert_eq!(job.error.as_deref(), Some("cancelled"));
+            assert_eq!(
+                job.history.last().unwrap().reason.as_deref(),
+                Some("cancelled")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_waiting_for_a_worker_are_dropped_when_cancelled() {
+        let limits = QueueLimits {
+            max_workers: 1,
+            ..QueueLimits::default()
+        };
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default())).with_limits(limits);
+        job_queue.add_job(sleep_job(), queued_job(0));
+        job_queue.add_job(sleep_job(), queued_job(1));
+
+        let cancel = job_queue.cancel_handle();
+        let (result, _) = tokio::join!(job_queue.run(), async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            assert!(cancel.cancel("1")); // while "0" holds the only worker
+        });
+        result.unwrap();
+
+        let jobs = job_queue.jobs();
+        assert_eq!(jobs[0].lock().unwrap().status, Status::Completed);
+        let dropped = jobs[1].lock().unwrap();
+        assert_eq!(dropped.status, Status::Cancelled);
+        assert_eq!(dropped.attempts, 0);
+        assert_eq!(dropped.start_timestamp, None);
+    }
+
+    #[tokio::test]
+    async fn jobs_not_started_at_shutdown_are_left_queued() {
+        let limits = QueueLimits {
+            max_workers: 1,
+            ..QueueLimits::default()
+        };
+        let (shutdown, signal) = watch::channel(false);
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()))
+            .with_limits(limits)
+            .with_shutdown(signal);
+        job_queue.add_job(sleep_job(), queued_job(0));
+        job_queue.add_job(sleep_job(), queued_job(1));
+
+        let (result, _) = tokio::join!(job_queue.run(), async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            shutdown.send_replace(true); // while "0" holds the only worker
+        });
+        result.unwrap();
+
+        let jobs = job_queue.jobs();
+        assert_eq!(jobs[0].lock().unwrap().status, Status::Completed);
+        let left = jobs[1].lock().unwrap();
+        assert_eq!(left.status, Status::Queued);
+        assert_eq!(left.attempts, 0);
+        assert!(left.history.is_empty());
+    }
+}
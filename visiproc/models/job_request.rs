@@ -1,14 +1,42 @@
 use crate::models::status::{deserialize_status, serialize_status, Status};
-use aws_sdk_dynamodb::types::AttributeValue;
-use eyre::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A request item written by the frontend, named after its attributes in the requests table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobRequest {
     pub id: String,
+    #[serde(rename = "requestID")]
     pub request_id: String, // db key
     pub author: String,
+    #[serde(rename = "jobName")]
+    pub name: String,
+    #[serde(rename = "jobDescription")]
+    pub description: String,
+    #[serde(rename = "analysisTypes")]
+    pub analysis_types: Vec<String>,
+    #[serde(rename = "creationDate")]
+    pub timestamp: i64, // db sort key
+    #[serde(
+        rename = "jobStatus",
+        serialize_with = "serialize_status",
+        deserialize_with = "deserialize_status"
+    )]
+    pub status: Status,
+    pub sources: Vec<String>,
+    #[serde(rename = "dateRangeStart")]
+    pub range_start: i64,
+    #[serde(rename = "dateRangeEnd")]
+    pub range_end: i64,
+    pub granularity: i32,
+}
+
+/// The state of a request as published to the update topics, which the frontend reads in
+/// `jobUpdateMessage.ts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestUpdate {
+    pub id: String,
+    pub request_id: String,
+    pub author: String,
     pub name: String,
     pub description: String,
     pub analysis_types: Vec<String>,
@@ -24,90 +52,90 @@ pub struct JobRequest {
     pub granularity: i32,
 }
 
-pub fn convert_item_to_job_request(item: &HashMap<String, AttributeValue>) -> Result<JobRequest> {
-    let job_request = JobRequest {
-        id: item
-            .get("id")
-            .ok_or_else(|| eyre::Error::msg("Missing id"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid id"))?
-            .to_owned(),
-        request_id: item
-            .get("requestID")
-            .ok_or_else(|| eyre::Error::msg("Missing requestID"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid requestID"))?
-            .to_owned(),
-        name: item
-            .get("jobName")
-            .ok_or_else(|| eyre::Error::msg("Missing jobName"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid jobName"))?
-            .to_owned(),
-        author: item
-            .get("author")
-            .ok_or_else(|| eyre::Error::msg("Missing author"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid author"))?
-            .to_owned(),
-        description: item
-            .get("jobDescription")
-            .ok_or_else(|| eyre::Error::msg("Missing jobDescription"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid jobDescription"))?
-            .to_owned(),
-        analysis_types: item
-            .get("analysisTypes")
-            .ok_or_else(|| eyre::Error::msg("Missing analysisTypes"))?
-            .as_l()
-            .map_err(|_| eyre::Error::msg("Invalid analysisTypes"))?
-            .iter()
-            .map(|v| v.as_s().unwrap().to_owned())
-            .collect(),
-        timestamp: item
-            .get("creationDate")
-            .ok_or_else(|| eyre::Error::msg("Missing creationDate"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid creationDate"))?
-            .parse::<i64>()
-            .map_err(|_| eyre::Error::msg("Invalid timestamp format"))?,
-        status: item
-            .get("jobStatus")
-            .ok_or_else(|| eyre::Error::msg("Missing jobStatus"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid jobStatus"))?
-            .parse::<Status>()
-            .map_err(|_| eyre::Error::msg("Failed to parse jobStatus into a Status enum"))?,
-        sources: item
-            .get("sources")
-            .ok_or_else(|| eyre::Error::msg("Missing sources"))?
-            .as_l()
-            .map_err(|_| eyre::Error::msg("Invalid sources"))?
-            .iter()
-            .map(|v| v.as_s().unwrap().to_owned())
-            .collect(),
-        range_start: item
-            .get("dateRangeStart")
-            .ok_or_else(|| eyre::Error::msg("Missing rangeStart"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid rangeStart"))?
-            .parse::<i64>()
-            .map_err(|_| eyre::Error::msg("Invalid timestamp format"))?,
-        range_end: item
-            .get("dateRangeEnd")
-            .ok_or_else(|| eyre::Error::msg("Missing rangeEnd"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid rangeEnd"))?
-            .parse::<i64>()
-            .map_err(|_| eyre::Error::msg("Invalid timestamp format"))?,
-        granularity: item
-            .get("granularity")
-            .ok_or_else(|| eyre::Error::msg("Missing granularity"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid granularity"))?
-            .parse::<i32>()
-            .map_err(|_| eyre::Error::msg("Invalid granularity format"))?,
-    };
+impl From<&JobRequest> for RequestUpdate {
+    fn from(request: &JobRequest) -> Self {
+        let request = request.clone();
+        RequestUpdate {
+            id: request.id,
+            request_id: request.request_id,
+            author: request.author,
+            name: request.name,
+            description: request.description,
+            analysis_types: request.analysis_types,
+            timestamp: request.timestamp,
+            status: request.status,
+            sources: request.sources,
+            range_start: request.range_start,
+            range_end: request.range_end,
+            granularity: request.granularity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::item::{from_item, to_item};
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use std::collections::HashMap;
+
+    fn s(value: &str) -> AttributeValue {
+        AttributeValue::S(value.to_string())
+    }
+
+    fn n(value: &str) -> AttributeValue {
+        AttributeValue::N(value.to_string())
+    }
+
+    /// A request item as the frontend writes it.
+    fn frontend_item() -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("id".to_string(), s("1")),
+            ("requestID".to_string(), s("abc")),
+            ("jobName".to_string(), s("Turbine load")),
+            ("jobDescription".to_string(), s("Weekly check")),
+            ("jobStatus".to_string(), s("PENDING")),
+            ("author".to_string(), s("ops")),
+            (
+                "analysisTypes".to_string(),
+                AttributeValue::L(vec![s("Exploratory Data Analysis")]),
+            ),
+            ("creationDate".to_string(), n("1700000000")),
+            ("sources".to_string(), AttributeValue::L(vec![s("turbine")])),
+            ("dateRangeStart".to_string(), n("1690000000")),
+            ("dateRangeEnd".to_string(), n("1700000000")),
+            ("granularity".to_string(), n("60")),
+            ("powerBILink".to_string(), s("https://example.com/report")),
+        ])
+    }
+
+    #[test]
+    fn frontend_items_round_trip() {
+        let request: JobRequest = from_item(&frontend_item()).unwrap();
+        assert_eq!(request.request_id, "abc");
+        assert_eq!(request.status, Status::Pending);
+        assert_eq!(request.timestamp, 1700000000);
+        assert_eq!(request.analysis_types, vec!["Exploratory Data Analysis"]);
+
+        let mut expected = frontend_item();
+        expected.remove("powerBILink"); // not used by visiproc
+        assert_eq!(to_item(&request).unwrap(), expected);
+    }
+
+    #[test]
+    fn invalid_attributes_are_named() {
+        let mut item = frontend_item();
+        item.insert("jobStatus".to_string(), n("1"));
+        let err = from_item::<JobRequest>(&item).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "jobStatus: invalid type: integer `1`, expected a string representing a job status"
+        );
 
-    Ok(job_request)
+        let mut item = frontend_item();
+        item.insert("creationDate".to_string(), s("yesterday"));
+        let err = from_item::<JobRequest>(&item).unwrap_err();
+        assert_eq!(err.path(), "creationDate");
+        assert!(err.to_string().contains("expected i64"), "{err}");
+    }
 }
@@ -1,101 +1,89 @@
 use crate::models::{
+    artifact::Artifact,
     job::Job,
     job_type::{deserialize_job_types, serialize_job_types, JobType},
     status::{deserialize_statuses, serialize_statuses, Status},
 };
-use aws_sdk_dynamodb::types::AttributeValue;
-use eyre::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobResponse {
+    #[serde(rename = "responseID")]
     pub response_id: String, // db key
-    pub request_id: String,  // points to the originating JobRequest
-    // pub start_timestamp: i64,
+    #[serde(rename = "requestID")]
+    pub request_id: String, // points to the originating JobRequest
+    pub start_timestamp: i64,
     pub end_timestamp: i64,
     #[serde(
+        rename = "jobType",
         serialize_with = "serialize_job_types",
         deserialize_with = "deserialize_job_types"
     )]
     pub job_type: Vec<JobType>,
 
     #[serde(
+        rename = "jobStatus",
         serialize_with = "serialize_statuses",
         deserialize_with = "deserialize_statuses"
     )]
-    pub job_status: Vec<Status>,
+    pub job_status: Vec<Status>, // status of each entry in `job_type`
+    pub artifacts: Vec<Artifact>, // uploaded results of every job
 }
 
-pub fn _create_job_response(
-    job: &Job,
-    job_types: Vec<JobType>,
-    job_status: Vec<Status>,
-) -> JobResponse {
-    // let start_timestamp = // TODO logic to get the start timestamp from response
+/// The response of a request whose analysis `jobs` have all finished, listing each job's type
+/// and status and every artifact they uploaded.
+pub fn create_job_response(request_id: &str, jobs: &[Job]) -> JobResponse {
     let end_timestamp = chrono::Utc::now().timestamp();
 
     JobResponse {
         response_id: uuid::Uuid::new_v4().to_string(),
-        request_id: job.request_id.clone(),
+        request_id: request_id.to_string(),
+        // jobs that never started (e.g. failed during recovery) ran for no time at all
+        start_timestamp: jobs
+            .iter()
+            .filter_map(|job| job.start_timestamp)
+            .min()
+            .unwrap_or(end_timestamp),
         end_timestamp,
-        job_type: job_types,
-        job_status,
+        job_type: jobs.iter().map(|job| job.job_type).collect(),
+        job_status: jobs.iter().map(|job| job.status.clone()).collect(),
+        artifacts: jobs
+            .iter()
+            .flat_map(|job| job.artifacts.iter().cloned())
+            .collect(),
     }
 }
 
-pub fn _convert_item_to_job_response(
-    item: &HashMap<String, AttributeValue>,
-) -> Result<JobResponse> {
-    let job_type_str = item
-        .get("jobType")
-        .ok_or_else(|| eyre::Error::msg("Missing jobType"))?
-        .as_s()
-        .map_err(|_| eyre::Error::msg("Invalid jobType"))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::item::{from_item, to_item};
+    use aws_sdk_dynamodb::types::AttributeValue;
 
-    let job_type: Vec<JobType> = serde_json::from_str(job_type_str)
-        .map_err(|_| eyre::Error::msg("Failed to deserialize jobType"))?;
+    #[test]
+    fn responses_round_trip() {
+        let response = JobResponse {
+            response_id: "r-1".to_string(),
+            request_id: "abc".to_string(),
+            start_timestamp: 1700000000,
+            end_timestamp: 1700000060,
+            job_type: vec![JobType::Eda, JobType::Corr],
+            job_status: vec![Status::Completed, Status::Failed],
+            artifacts: Vec::new(),
+        };
+        let item = to_item(&response).unwrap();
+        assert_eq!(
+            item["jobStatus"],
+            AttributeValue::L(vec![
+                AttributeValue::S("COMPLETE".to_string()),
+                AttributeValue::S("FAILED".to_string()),
+            ])
+        );
+        assert_eq!(item["artifacts"], AttributeValue::L(Vec::new()));
 
-    let job_status_str = item
-        .get("jobStatus")
-        .ok_or_else(|| eyre::Error::msg("Missing jobStatus"))?
-        .as_s()
-        .map_err(|_| eyre::Error::msg("Invalid jobStatus"))?;
-
-    let job_status: Vec<Status> = serde_json::from_str(job_status_str)
-        .map_err(|_| eyre::Error::msg("Failed to deserialize jobStatus"))?;
-
-    let response = JobResponse {
-        response_id: item
-            .get("responseID")
-            .ok_or_else(|| eyre::Error::msg("Missing responseID"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid responseID"))?
-            .to_owned(),
-        request_id: item
-            .get("requestID")
-            .ok_or_else(|| eyre::Error::msg("Missing requestID"))?
-            .as_s()
-            .map_err(|_| eyre::Error::msg("Invalid requestID"))?
-            .to_owned(),
-        // start_timestamp: item
-        //     .get("startTimestamp")
-        //     .ok_or_else(|| eyre::Error::msg("Missing startTimestamp"))?
-        //     .as_n()
-        //     .map_err(|_| eyre::Error::msg("Invalid startTimestamp"))?
-        //     .parse::<i64>()
-        //     .map_err(|_| eyre::Error::msg("Invalid start timestamp format"))?,
-        end_timestamp: item
-            .get("endTimestamp")
-            .ok_or_else(|| eyre::Error::msg("Missing endTimestamp"))?
-            .as_n()
-            .map_err(|_| eyre::Error::msg("Invalid endTimestamp"))?
-            .parse::<i64>()
-            .map_err(|_| eyre::Error::msg("Invalid end timestamp format"))?,
-
-        job_type,
-        job_status,
-    };
-
-    Ok(response)
+        let read: JobResponse = from_item(&item).unwrap();
+        assert_eq!(read.job_type, response.job_type);
+        assert_eq!(read.job_status, response.job_status);
+        assert_eq!(to_item(&read).unwrap(), item);
+    }
 }
@@ -6,7 +6,7 @@ use serde::{
 };
 use std::{error::Error, fmt, str::FromStr};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum JobType {
     Eda,
     Corr,
@@ -55,24 +55,37 @@ impl fmt::Display for JobType {
 }
 
 impl JobType {
+    /// The known analysis types of `job_request`, each listed once.
     pub fn from_request(job_request: &JobRequest) -> Vec<Self> {
-        job_request
-            .analysis_types
-            .iter()
-            .filter_map(|analysis_type| {
-                match analysis_type.as_str() {
-                    "Exploratory Data Analysis" => Some(JobType::Eda),
-                    "Correlation" => Some(JobType::Corr),
-                    "Simulated Job" => Some(JobType::SimulatedJob),
-                    "Simulated Error" => Some(JobType::SimulatedError),
-                    _ => None, // Skip unknown analysis types
-                }
-            })
-            .collect()
+        let mut job_types = Vec::new();
+        for analysis_type in job_request.analysis_types.iter() {
+            let job_type = match analysis_type.as_str() {
+                "Exploratory Data Analysis" => JobType::Eda,
+                "Correlation" => JobType::Corr,
+                "Simulated Job" => JobType::SimulatedJob,
+                "Simulated Error" => JobType::SimulatedError,
+                _ => continue, // Skip unknown analysis types
+            };
+            if !job_types.contains(&job_type) {
+                job_types.push(job_type);
+            }
+        }
+        job_types
+    }
+
+    /// Short name used in the ids of this type's jobs.
+    pub fn slug(&self) -> &'static str {
+        match self {
+            JobType::Eda => "eda",
+            JobType::Corr => "correlation",
+            JobType::SimulatedJob => "simulated",
+            JobType::SimulatedError => "simulated-error",
+            JobType::None => "none",
+        }
     }
 }
 
-pub fn _serialize_job_type<S>(job_type: &JobType, serializer: S) -> Result<S::Ok, S::Error>
+pub fn serialize_job_type<S>(job_type: &JobType, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -87,7 +100,7 @@ where
     serializer.serialize_some(&job_types_str)
 }
 
-pub fn _deserialize_job_type<'de, D>(deserializer: D) -> Result<JobType, D::Error>
+pub fn deserialize_job_type<'de, D>(deserializer: D) -> Result<JobType, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -97,7 +110,7 @@ where
         type Value = JobType;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a string representing a job job_type")
+            formatter.write_str("a string representing a job type")
         }
 
         fn visit_str<E>(self, value: &str) -> Result<JobType, E>
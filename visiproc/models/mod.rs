@@ -1,3 +1,4 @@
+pub mod artifact;
 pub mod data;
 pub mod job;
 pub mod job_queue;
@@ -10,8 +10,11 @@ pub enum Status {
     Pending,
     Queued,
     Processing,
+    Retrying,
     Completed,
     Failed,
+    CancelRequested,
+    Cancelled,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +28,21 @@ impl fmt::Display for ParseStatusError {
 
 impl Error for ParseStatusError {}
 
+/// A status change that `Status::transition` does not allow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidTransition {
+    pub from: Status,
+    pub to: Status,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot move from {} to {}", self.from, self.to)
+    }
+}
+
+impl Error for InvalidTransition {}
+
 impl FromStr for Status {
     type Err = ParseStatusError;
 
@@ -33,8 +51,11 @@ impl FromStr for Status {
             "PENDING" => Ok(Status::Pending),
             "QUEUED" => Ok(Status::Queued),
             "PROCESSING" => Ok(Status::Processing),
+            "RETRYING" => Ok(Status::Retrying),
             "COMPLETE" => Ok(Status::Completed),
             "FAILED" => Ok(Status::Failed),
+            "CANCEL_REQUESTED" => Ok(Status::CancelRequested),
+            "CANCELLED" => Ok(Status::Cancelled),
             _ => Err(ParseStatusError),
         }
     }
@@ -46,20 +67,73 @@ impl fmt::Display for Status {
             Status::Pending => "PENDING",
             Status::Queued => "QUEUED",
             Status::Processing => "PROCESSING",
+            Status::Retrying => "RETRYING",
             Status::Completed => "COMPLETE",
             Status::Failed => "FAILED",
+            Status::CancelRequested => "CANCEL_REQUESTED",
+            Status::Cancelled => "CANCELLED",
         };
         write!(f, "{}", status_str)
     }
 }
 
 impl Status {
-    pub fn next(&self) -> Option<Status> {
-        match self {
-            Status::Pending => Some(Status::Queued),
-            Status::Queued => Some(Status::Processing),
-            Status::Processing => Some(Status::Completed),
-            Status::Completed | Status::Failed => None,
+    /// Whether a request or job with this status may move to `to`.
+    ///
+    /// Anything unfinished can fail or be cancelled. A failed run is RETRYING until its next
+    /// attempt starts, and an interrupted run is re-queued. A request whose jobs could not be queued
+    /// is released back to PENDING. A user asks to stop a request by moving it to CANCEL_REQUESTED,
+    /// after which it still ends with the rollup of whatever its jobs did.
+    /// COMPLETE, FAILED and CANCELLED are final.
+    pub fn can_transition(&self, to: &Status) -> bool {
+        use Status::*;
+        match (self, to) {
+            (Pending | Queued | Processing | Retrying, Failed | Cancelled) => true,
+            (Pending, Queued) => true,
+            (Queued, Pending) => true, // claim released after its jobs failed to queue
+            (Queued | Retrying, Processing) => true,
+            (Processing, Retrying | Completed) => true,
+            (Processing | Retrying, Queued) => true, // re-queued after a restart
+            (Pending | Queued | Processing, CancelRequested) => true,
+            (CancelRequested, Completed | Failed | Cancelled) => true,
+            _ => false,
+        }
+    }
+
+    pub fn transition(&self, to: Status) -> Result<Status, InvalidTransition> {
+        if self.can_transition(&to) {
+            Ok(to)
+        } else {
+            Err(InvalidTransition {
+                from: self.clone(),
+                to,
+            })
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, Status::Completed | Status::Failed | Status::Cancelled)
+    }
+
+    /// The status of a request whose analysis jobs have `statuses`.
+    ///
+    /// While any job is unfinished the request is QUEUED until one of them starts, then
+    /// PROCESSING. Once all are final it is COMPLETE only if every job completed, FAILED if any
+    /// failed and CANCELLED otherwise. A request without jobs has nothing to run and is FAILED.
+    pub fn rollup(statuses: &[Status]) -> Status {
+        use Status::*;
+        if statuses.is_empty() {
+            Failed
+        } else if statuses.iter().all(|s| matches!(s, Pending | Queued)) {
+            Queued
+        } else if !statuses.iter().all(Status::is_final) {
+            Processing
+        } else if statuses.iter().all(|s| *s == Completed) {
+            Completed
+        } else if statuses.contains(&Failed) {
+            Failed
+        } else {
+            Cancelled
         }
     }
 }
@@ -136,3 +210,93 @@ where
 
     deserializer.deserialize_seq(StatusesVisitor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Status; 8] = [
+        Status::Pending,
+        Status::Queued,
+        Status::Processing,
+        Status::Retrying,
+        Status::Completed,
+        Status::Failed,
+        Status::CancelRequested,
+        Status::Cancelled,
+    ];
+
+    #[test]
+    fn statuses_round_trip_through_strings() {
+        for status in ALL {
+            assert_eq!(status.to_string().parse::<Status>().unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn runs_move_forward_retry_and_requeue() {
+        let path = [
+            Status::Pending,
+            Status::Queued,
+            Status::Processing,
+            Status::Retrying,
+            Status::Processing,
+            Status::Queued,
+            Status::Processing,
+            Status::Completed,
+        ];
+        for pair in path.windows(2) {
+            assert_eq!(pair[0].transition(pair[1].clone()), Ok(pair[1].clone()));
+        }
+        assert!(Status::Retrying.can_transition(&Status::Cancelled));
+        assert!(Status::Pending.can_transition(&Status::Failed));
+        assert!(Status::Queued.can_transition(&Status::Pending));
+    }
+
+    #[test]
+    fn cancel_requests_end_with_the_rollup() {
+        use Status::*;
+        assert!(Processing.can_transition(&CancelRequested));
+        assert!(!Completed.can_transition(&CancelRequested));
+        assert!(!CancelRequested.can_transition(&Processing));
+        for to in [Completed, Failed, Cancelled] {
+            assert!(CancelRequested.can_transition(&to), "{to}");
+        }
+        assert!(!CancelRequested.is_final());
+    }
+
+    #[test]
+    fn final_statuses_never_change() {
+        for from in ALL.iter().filter(|status| status.is_final()) {
+            for to in ALL {
+                assert!(!from.can_transition(&to), "{from} -> {to}");
+            }
+        }
+        assert_eq!(
+            Status::Pending.transition(Status::Completed),
+            Err(InvalidTransition {
+                from: Status::Pending,
+                to: Status::Completed,
+            })
+        );
+        assert_eq!(
+            Status::Completed
+                .transition(Status::Failed)
+                .unwrap_err()
+                .to_string(),
+            "cannot move from COMPLETE to FAILED"
+        );
+    }
+
+    #[test]
+    fn requests_roll_up_their_jobs() {
+        use Status::*;
+        assert_eq!(Status::rollup(&[Queued, Queued]), Queued);
+        assert_eq!(Status::rollup(&[Completed, Queued]), Processing);
+        assert_eq!(Status::rollup(&[Failed, Retrying]), Processing);
+        assert_eq!(Status::rollup(&[Completed, Completed]), Completed);
+        assert_eq!(Status::rollup(&[Completed, Failed, Cancelled]), Failed);
+        assert_eq!(Status::rollup(&[Completed, Cancelled]), Cancelled);
+        assert_eq!(Status::rollup(&[]), Failed);
+    }
+}
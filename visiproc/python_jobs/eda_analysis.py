@@ -5,16 +5,47 @@ Time-series profiling for VisiLake
 import sys
 import os
 import pandas as pd
-import tempfile
-import subprocess
 import gzip
-import urllib.request
 import json
 import csv
 
+import boto3
+from botocore.config import Config
 from ydata_profiling import ProfileReport
 
-s3url = "http://s3.us-east-1.localhost.localstack.cloud:4566/metadata"
+from manifest import EXIT_VALIDATION_ERROR, artifact, emit_manifest
+
+# visiproc passes the ydata-profiling settings in, falling back to the ones next to this script
+config_file = os.environ.get(
+    "VISIPROC_EDA_CONFIG", os.path.join(os.path.dirname(__file__), "ydata-config.yaml")
+)
+
+
+def s3_client():
+    """An S3 client reaching the endpoint and region visiproc uses, as set by the runner."""
+    endpoint_url = os.environ.get("AWS_ENDPOINT_URL_S3")
+    return boto3.client(
+        "s3",
+        endpoint_url=endpoint_url,
+        region_name=os.environ.get("AWS_REGION"),
+        # custom endpoints like localstack address buckets by path, as visiproc does
+        config=Config(s3={"addressing_style": "path"}) if endpoint_url else None,
+    )
+
+
+def read_athena_output(directory):
+    """Reads the records of the gzipped Athena output under `directory`, an s3://bucket/prefix/ uri."""
+    bucket, _, prefix = directory.removeprefix("s3://").partition("/")
+    s3 = s3_client()
+    listing = s3.list_objects_v2(Bucket=bucket, Prefix=prefix)
+    keys = [item["Key"] for item in listing.get("Contents", []) if item["Key"].endswith(".gz")]
+    if not keys:
+        print(f"No Athena output found in {directory}", file=sys.stderr)
+        sys.exit(EXIT_VALIDATION_ERROR)
+
+    body = s3.get_object(Bucket=bucket, Key=keys[-1])["Body"].read()
+    content = gzip.decompress(body)
+    return [json.loads(record) for record in content.decode("utf-8").strip().split("\n")]
 
 
 def convert_parquet_to_csv(parquet_path, csv_path):
@@ -30,24 +61,17 @@ def convert_csv_to_parquet(csv_path, parquet_path):
 
 
 def eda_analysis(directory, request_id):
-    athenaFileLs = os.popen(f"awslocal s3 ls {directory}").read()
-    athenaFileName = athenaFileLs.split(" ")[-1]
-    subprocess.call(f"mkdir -p outputs/{request_id}", shell=True)
-
-    with urllib.request.urlopen(f"{s3url}/{request_id}/{athenaFileName}") as response:
-        content = gzip.decompress(response.read())
-
-        json_strings = []
-        for record in content.decode("utf-8").strip().rstrip("\n").split("\n"):
-            json_strings.append(json.loads(record))
-
-        with open(f"./outputs/{request_id}/{request_id}.csv", mode="w") as csv_file:
-            csv_writer = csv.DictWriter(csv_file, fieldnames=json_strings[0].keys())
-            csv_writer.writeheader()
-            for record in json_strings:
-                csv_writer.writerow(record)
-
-    csv_path = f"./outputs/{request_id}/{request_id}.csv"
+    # visiproc uploads the artifacts by their path in this directory
+    output_dir = os.environ.get("VISIPROC_OUTPUT_DIR", f"outputs/{request_id}")
+    os.makedirs(output_dir, exist_ok=True)
+
+    records = read_athena_output(directory)
+    csv_path = f"{output_dir}/{request_id}.csv"
+    with open(csv_path, mode="w") as csv_file:
+        csv_writer = csv.DictWriter(csv_file, fieldnames=records[0].keys())
+        csv_writer.writeheader()
+        for record in records:
+            csv_writer.writerow(record)
 
     # load the dataset from the found or converted CSV file
     df = pd.read_csv(csv_path, index_col=0)
@@ -60,34 +84,39 @@ def eda_analysis(directory, request_id):
         tsmode=True,
         sortby="date local",
         title=f"Profile for {request_id}",
-        config_file="python_jobs/ydata-config.yaml",
+        config_file=config_file,
     )
 
-    directory = f"./outputs/{request_id}"
-
-    # Create a temporary file for the report
-    with tempfile.NamedTemporaryFile(
-        delete=False,
-        mode="w",
-        suffix=".html",
-        dir=directory,
-        encoding="utf-8",
-        prefix=f"{request_id}-eda-",
-    ) as tmpfile:
-        profile.to_file(tmpfile.name)
-        print(
-            tmpfile.name
-        )  ## job_queue.rs reads stdout to get the location of the eda report
-
-    convert_csv_to_parquet(
-        csv_path, f"./outputs/{request_id}/{request_id}-data.parquet"
+    # the frontend links to these file names
+    report_path = f"{output_dir}/{request_id}-eda.html"
+    data_path = f"{output_dir}/{request_id}-data.parquet"
+
+    profile.to_file(report_path)
+    convert_csv_to_parquet(csv_path, data_path)
+
+    warnings = []
+    if len(df) > 1000:
+        warnings.append(f"Profiled the first 1000 of {len(df)} rows")
+
+    emit_manifest(
+        [
+            artifact("report", report_path, "text/html", "Exploratory data analysis report"),
+            artifact(
+                "data",
+                data_path,
+                "application/vnd.apache.parquet",
+                "Data the report was built from",
+            ),
+        ],
+        metrics={"rows": len(df), "columns": len(df.columns)},
+        warnings=warnings,
     )
 
 
 if __name__ == "__main__":
     if len(sys.argv) != 3:
-        print("Usage: python eda_analysis.py <directory> <request_id>")
-        sys.exit(1)
+        print("Usage: python eda_analysis.py <directory> <request_id>", file=sys.stderr)
+        sys.exit(EXIT_VALIDATION_ERROR)
 
     directory = sys.argv[1]
     request_id = sys.argv[2]
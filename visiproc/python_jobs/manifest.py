@@ -0,0 +1,28 @@
+"""
+Result manifest printed by analysis scripts for visiproc
+"""
+
+import json
+
+# visiproc never retries a job whose script exits with this code
+EXIT_VALIDATION_ERROR = 2
+
+
+def artifact(kind, path, mime_type, description=""):
+    """Describes a file produced by the analysis, uploaded by visiproc under its path in VISIPROC_OUTPUT_DIR."""
+    return {
+        "kind": kind,
+        "path": path,
+        "mime_type": mime_type,
+        "description": description,
+    }
+
+
+def emit_manifest(artifacts, metrics=None, warnings=None):
+    """Prints the manifest as the last line on stdout, where visiproc reads it from."""
+    manifest = {
+        "artifacts": artifacts,
+        "metrics": metrics or {},
+        "warnings": warnings or [],
+    }
+    print(json.dumps(manifest), flush=True)
@@ -2,30 +2,41 @@ import sys
 import os
 import tempfile
 
+from manifest import EXIT_VALIDATION_ERROR, artifact, emit_manifest
+
 
 def simulate_job(directory, request_id):
     print("Simulating job run with the following metadata:", file=sys.stderr)
     print(f"Path: {directory}", file=sys.stderr)
     print(f"Request id: {request_id}", file=sys.stderr)
 
-    directory = f"outputs/{request_id}/"
-    if not os.path.exists(directory):
-        os.makedirs(directory)
+    output_dir = os.environ.get("VISIPROC_OUTPUT_DIR", f"outputs/{request_id}")
+    os.makedirs(output_dir, exist_ok=True)
 
     try:
         with tempfile.NamedTemporaryFile(
             delete=False,
             mode="w",
-            suffix=".parquet",
-            dir=directory,
+            suffix=".txt",
+            dir=output_dir,
             prefix=f"{request_id}-data",
         ) as tmpfile:
             tmpfile.write(f"Temporary data based on input from {directory}\n")
             tmpfile.write(f"This is a temporary simulated output for {request_id}.\n")
-            # Print the path of the temporary file to stdout
-            print(tmpfile.name)
             print("Simulated processing done.", file=sys.stderr)
 
+        emit_manifest(
+            [
+                artifact(
+                    "data",
+                    tmpfile.name,
+                    "text/plain",
+                    "Simulated output data",
+                )
+            ],
+            metrics={"rows": 2},
+        )
+
     except Exception as e:
         print(f"Error writing the file: {e}", file=sys.stderr)
 
@@ -36,7 +47,7 @@ if __name__ == "__main__":
             "Usage: python simulated_analysis.py <directory> <request_id>",
             file=sys.stderr,
         )
-        sys.exit(1)
+        sys.exit(EXIT_VALIDATION_ERROR)
 
     directory = sys.argv[1]
     request_id = sys.argv[2]
@@ -0,0 +1,303 @@
+//! In-memory stores, so tasks and the job queue can be tested without AWS.
+use super::{
+    BlobStore, EventPublisher, JobStore, Message, MessageQueue, QueryEngine, RequestStore,
+    StoreFuture,
+};
+use crate::models::{job::Job, job_request::JobRequest, job_response::JobResponse, status::Status};
+use std::{collections::HashMap, path::Path, sync::Mutex, time::Duration};
+
+#[derive(Default)]
+pub struct MemoryRequests {
+    requests: Mutex<Vec<JobRequest>>,
+}
+
+impl MemoryRequests {
+    pub fn new(requests: Vec<JobRequest>) -> Self {
+        MemoryRequests {
+            requests: Mutex::new(requests),
+        }
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<JobRequest> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|request| request.request_id == request_id)
+            .cloned()
+    }
+}
+
+impl RequestStore for MemoryRequests {
+    fn requests_with_status(&self, status: Status) -> StoreFuture<'_, Vec<JobRequest>> {
+        let requests = self.requests.lock().unwrap();
+        let found = requests
+            .iter()
+            .filter(|request| request.status == status)
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn requests_with_id<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<JobRequest>> {
+        let found = self.get(request_id).into_iter().collect();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn set_status<'a>(
+        &'a self,
+        request: &'a JobRequest,
+        status: Status,
+    ) -> StoreFuture<'a, Option<JobRequest>> {
+        let mut requests = self.requests.lock().unwrap();
+        let updated = requests
+            .iter_mut()
+            .find(|stored| stored.request_id == request.request_id)
+            .map(|stored| {
+                (stored.status == request.status).then(|| {
+                    stored.status = status;
+                    stored.clone()
+                })
+            })
+            .ok_or_else(|| eyre::eyre!("No request {}", request.request_id));
+        Box::pin(async move { updated })
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryJobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    responses: Mutex<Vec<JobResponse>>,
+}
+
+impl MemoryJobs {
+    pub fn new(jobs: Vec<Job>) -> Self {
+        let jobs = jobs.into_iter().map(|job| (job.job_id.clone(), job));
+        MemoryJobs {
+            jobs: Mutex::new(jobs.collect()),
+            responses: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(job_id).cloned()
+    }
+
+    pub fn responses(&self) -> Vec<JobResponse> {
+        self.responses.lock().unwrap().clone()
+    }
+}
+
+impl JobStore for MemoryJobs {
+    fn put_job<'a>(&'a self, job: &'a Job) -> StoreFuture<'a, ()> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(job.job_id.clone(), job.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn unfinished_jobs<'a>(&'a self, worker_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+        let jobs = self.jobs.lock().unwrap();
+        let found = jobs
+            .values()
+            .filter(|job| job.worker_id == worker_id)
+            .filter(|job| {
+                matches!(
+                    job.status,
+                    Status::Queued | Status::Processing | Status::Retrying
+                )
+            })
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn request_jobs<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+        let jobs = self.jobs.lock().unwrap();
+        let found = jobs
+            .values()
+            .filter(|job| job.request_id == request_id)
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn put_response<'a>(&'a self, response: &'a JobResponse) -> StoreFuture<'a, ()> {
+        self.responses.lock().unwrap().push(response.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// An uploaded object with its content type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blob {
+    pub body: Vec<u8>,
+    pub mime_type: String,
+}
+
+#[derive(Default)]
+pub struct MemoryBlobs {
+    blobs: Mutex<HashMap<String, Blob>>, // by s3 uri
+}
+
+impl MemoryBlobs {
+    pub fn get(&self, uri: &str) -> Option<Blob> {
+        self.blobs.lock().unwrap().get(uri).cloned()
+    }
+
+    fn insert(&self, bucket: &str, key: &str, body: Vec<u8>, mime_type: &str) {
+        let blob = Blob {
+            body,
+            mime_type: mime_type.to_string(),
+        };
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert(format!("s3://{}/{}", bucket, key), blob);
+    }
+}
+
+impl BlobStore for MemoryBlobs {
+    fn put_file<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        path: &'a Path,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let body = tokio::fs::read(path).await?;
+            self.insert(bucket, key, body, mime_type);
+            Ok(())
+        })
+    }
+
+    fn put_bytes<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        body: Vec<u8>,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()> {
+        self.insert(bucket, key, body, mime_type);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+pub struct MemoryPublisher {
+    topics: Vec<String>,
+    messages: Mutex<Vec<(String, String)>>, // topic and message, in publishing order
+}
+
+impl MemoryPublisher {
+    pub fn new(topics: &[&str]) -> Self {
+        MemoryPublisher {
+            topics: topics.iter().map(|topic| topic.to_string()).collect(),
+            messages: Mutex::default(),
+        }
+    }
+
+    pub fn messages(&self) -> Vec<(String, String)> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+impl EventPublisher for MemoryPublisher {
+    fn topics(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async { Ok(self.topics.clone()) })
+    }
+
+    fn publish<'a>(&'a self, topic: &'a str, message: &'a str) -> StoreFuture<'a, ()> {
+        self.messages
+            .lock()
+            .unwrap()
+            .push((topic.to_string(), message.to_string()));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Hands out every message it holds on the first receive and remembers what was done with them.
+#[derive(Default)]
+pub struct MemoryMessages {
+    pending: Mutex<Vec<Message>>,
+    deleted: Mutex<Vec<String>>,  // ids of deleted messages, in order
+    extended: Mutex<Vec<String>>, // ids of messages whose lease was extended, in order
+}
+
+impl MemoryMessages {
+    pub fn new(bodies: &[&str]) -> Self {
+        let pending = bodies
+            .iter()
+            .enumerate()
+            .map(|(index, body)| Message {
+                id: index.to_string(),
+                body: body.to_string(),
+                receipt: format!("receipt-{index}"),
+            })
+            .collect();
+        MemoryMessages {
+            pending: Mutex::new(pending),
+            ..MemoryMessages::default()
+        }
+    }
+
+    pub fn deleted(&self) -> Vec<String> {
+        self.deleted.lock().unwrap().clone()
+    }
+
+    pub fn extended(&self) -> Vec<String> {
+        self.extended.lock().unwrap().clone()
+    }
+}
+
+impl MessageQueue for MemoryMessages {
+    fn receive(&self) -> StoreFuture<'_, Vec<Message>> {
+        let received = std::mem::take(&mut *self.pending.lock().unwrap());
+        Box::pin(async move { Ok(received) })
+    }
+
+    fn delete<'a>(&'a self, message: &'a Message) -> StoreFuture<'a, ()> {
+        self.deleted.lock().unwrap().push(message.id.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn extend_lease<'a>(&'a self, message: &'a Message, _lease: Duration) -> StoreFuture<'a, ()> {
+        self.extended.lock().unwrap().push(message.id.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Answers queries with the rows it was given for them, and no rows for any other query.
+#[derive(Default)]
+pub struct MemoryQueries {
+    results: HashMap<String, Vec<Vec<String>>>, // rows by query
+    queries: Mutex<Vec<(String, String)>>,      // query and database, in the order they ran
+}
+
+impl MemoryQueries {
+    pub fn new(results: Vec<(&str, Vec<Vec<String>>)>) -> Self {
+        MemoryQueries {
+            results: results
+                .into_iter()
+                .map(|(query, rows)| (query.to_string(), rows))
+                .collect(),
+            queries: Mutex::default(),
+        }
+    }
+
+    pub fn queries(&self) -> Vec<(String, String)> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+impl QueryEngine for MemoryQueries {
+    fn query<'a>(&'a self, query: &'a str, database: &'a str) -> StoreFuture<'a, Vec<Vec<String>>> {
+        self.queries
+            .lock()
+            .unwrap()
+            .push((query.to_string(), database.to_string()));
+        let rows = self.results.get(query).cloned().unwrap_or_default();
+        Box::pin(async move { Ok(rows) })
+    }
+}
@@ -0,0 +1,82 @@
+//! Where visiproc keeps its state and sends its events. Tasks and the job queue only see these
+//! traits; the AWS backed implementations live next to the functions they wrap in `crate::aws`.
+#[cfg(test)]
+pub mod memory;
+
+use crate::models::{job::Job, job_request::JobRequest, job_response::JobResponse, status::Status};
+use eyre::Result;
+use std::{future::Future, path::Path, pin::Pin, time::Duration};
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// The requests submitted by the frontend.
+pub trait RequestStore: Send + Sync {
+    fn requests_with_status(&self, status: Status) -> StoreFuture<'_, Vec<JobRequest>>;
+    fn requests_with_id<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<JobRequest>>;
+    /// Sets the status of `request`, returning the updated request, or `None` when the stored
+    /// status is no longer `request.status` because another worker changed it first.
+    fn set_status<'a>(
+        &'a self,
+        request: &'a JobRequest,
+        status: Status,
+    ) -> StoreFuture<'a, Option<JobRequest>>;
+}
+
+/// Job records and the responses recorded for finished jobs.
+pub trait JobStore: Send + Sync {
+    fn put_job<'a>(&'a self, job: &'a Job) -> StoreFuture<'a, ()>;
+    /// Jobs owned by `worker_id` that are still QUEUED, PROCESSING or RETRYING.
+    fn unfinished_jobs<'a>(&'a self, worker_id: &'a str) -> StoreFuture<'a, Vec<Job>>;
+    /// Every job of the request, one per analysis type.
+    fn request_jobs<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<Job>>;
+    fn put_response<'a>(&'a self, response: &'a JobResponse) -> StoreFuture<'a, ()>;
+}
+
+/// Objects holding job inputs and results.
+pub trait BlobStore: Send + Sync {
+    /// Uploads the local file at `path` to `bucket`/`key`.
+    fn put_file<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        path: &'a Path,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()>;
+    fn put_bytes<'a>(
+        &'a self,
+        bucket: &'a str,
+        key: &'a str,
+        body: Vec<u8>,
+        mime_type: &'a str,
+    ) -> StoreFuture<'a, ()>;
+}
+
+/// Topics that request updates are published to.
+pub trait EventPublisher: Send + Sync {
+    fn topics(&self) -> StoreFuture<'_, Vec<String>>;
+    fn publish<'a>(&'a self, topic: &'a str, message: &'a str) -> StoreFuture<'a, ()>;
+}
+
+/// A message received from a `MessageQueue`, hidden from other consumers while it is leased.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: String,
+    pub body: String,
+    pub receipt: String, // identifies this delivery when deleting the message or extending its lease
+}
+
+/// The queue that requests are sent to for intake mode. A received message comes back once its
+/// lease runs out, unless it is deleted first.
+pub trait MessageQueue: Send + Sync {
+    /// Waits for the next batch of messages, which may be empty.
+    fn receive(&self) -> StoreFuture<'_, Vec<Message>>;
+    fn delete<'a>(&'a self, message: &'a Message) -> StoreFuture<'a, ()>;
+    /// Keeps `message` hidden for another `lease` from now.
+    fn extend_lease<'a>(&'a self, message: &'a Message, lease: Duration) -> StoreFuture<'a, ()>;
+}
+
+/// Runs SQL over the datalake.
+pub trait QueryEngine: Send + Sync {
+    /// Runs `query` against `database` to completion, returning its rows with the header first.
+    fn query<'a>(&'a self, query: &'a str, database: &'a str) -> StoreFuture<'a, Vec<Vec<String>>>;
+}
@@ -0,0 +1,208 @@
+use crate::{
+    models::{job_queue::JobQueue, status::Status},
+    store::{EventPublisher, JobStore, RequestStore},
+    tasks::queue::{follow_jobs, publish_request, update_request_status},
+};
+use eyre::Result;
+use log::{debug, error, info};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Stops every request a user moved to CANCEL_REQUESTED.
+///
+/// Unfinished jobs of the request in `job_queue` are cancelled, so queued ones are dropped and
+/// running ones killed; the request then moves to their rollup when the queue is published.
+/// A request that was never queued is CANCELLED right away, and one whose jobs all finished
+/// before the cancellation arrived moves to their rollup. Requests whose jobs are still being
+/// run by another worker are left to that worker.
+pub async fn cancel_requested(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    topics: &[String],
+    job_queue: &JobQueue,
+) -> Result<()> {
+    let cancel = job_queue.cancel_handle();
+    let queued = job_queue.jobs();
+
+    for job_request in requests
+        .requests_with_status(Status::CancelRequested)
+        .await?
+    {
+        let request_id = job_request.request_id.clone();
+        let in_queue: Vec<_> = queued
+            .iter()
+            .map(|job_metadata| job_metadata.lock().unwrap().clone())
+            .filter(|job| job.request_id == request_id)
+            .collect();
+        if !in_queue.is_empty() {
+            for job in in_queue.iter().filter(|job| !job.status.is_final()) {
+                info!("Cancelling job {} of request {}", job.job_id, request_id);
+                cancel.cancel(&job.job_id);
+            }
+            continue;
+        }
+
+        let request_jobs = jobs.request_jobs(&request_id).await?;
+        if request_jobs.is_empty() {
+            // never queued, so there is nothing to stop
+            if let Some(job_request) =
+                update_request_status(requests, &job_request, Status::Cancelled).await?
+            {
+                info!("Cancelled request {}", request_id);
+                publish_request(publisher, topics, &job_request).await?;
+            }
+        } else if request_jobs.iter().all(|job| job.status.is_final()) {
+            follow_jobs(requests, publisher, topics, job_request, &request_jobs).await?;
+        } else {
+            debug!("Request {} is being run by another worker", request_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `cancel_requested` every `interval`, logging failures, until the future is dropped.
+/// Meant to be raced against `JobQueue::run` so that running jobs can be stopped.
+pub async fn watch_cancellations(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    topics: &[String],
+    job_queue: &JobQueue,
+    interval: Duration,
+) {
+    loop {
+        sleep(interval).await;
+        if let Err(err) = cancel_requested(requests, jobs, publisher, topics, job_queue).await {
+            error!("Checking for cancelled requests failed: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        models::{
+            job::create_job_from_request,
+            job_request::{JobRequest, RequestUpdate},
+            job_type::JobType,
+        },
+        store::memory::{MemoryBlobs, MemoryJobs, MemoryPublisher, MemoryRequests},
+        tasks::queue::{publish_complete_requests, queue_new_requests},
+    };
+    use std::sync::Arc;
+
+    fn request(request_id: &str, status: Status) -> JobRequest {
+        JobRequest {
+            analysis_types: vec!["Simulated Job".to_string()],
+            id: request_id.to_string(),
+            request_id: request_id.to_string(),
+            author: "test author".to_string(),
+            name: "test job".to_string(),
+            description: "test desc".to_string(),
+            timestamp: 0,
+            status,
+            sources: vec!["simulated".to_string()],
+            range_start: 0,
+            range_end: 1,
+            granularity: 0,
+        }
+    }
+
+    fn published(publisher: &MemoryPublisher) -> Vec<(String, Status)> {
+        publisher
+            .messages()
+            .iter()
+            .map(|(_, message)| {
+                let update: RequestUpdate = serde_json::from_str(message).unwrap();
+                (update.request_id, update.status)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn queued_requests_are_cancelled_before_they_run() -> Result<()> {
+        let requests = MemoryRequests::new(vec![request("queued", Status::Pending)]);
+        let jobs = Arc::new(MemoryJobs::default());
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let topics = publisher.topics().await?;
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        let config = Config::default();
+        queue_new_requests(&requests, &publisher, &config, &topics, &mut job_queue).await?;
+        let queued = requests.get("queued").unwrap();
+        requests
+            .set_status(&queued, Status::CancelRequested)
+            .await?;
+
+        cancel_requested(&requests, jobs.as_ref(), &publisher, &topics, &job_queue).await?;
+        job_queue.run().await?;
+        publish_complete_requests(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+
+        let job = jobs.get("queued-simulated").unwrap();
+        assert_eq!(job.status, Status::Cancelled);
+        assert_eq!(job.attempts, 0);
+        assert_eq!(requests.get("queued").unwrap().status, Status::Cancelled);
+        assert_eq!(
+            published(&publisher),
+            vec![
+                ("queued".to_string(), Status::Queued),
+                ("queued".to_string(), Status::Cancelled),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_outside_the_queue_are_cancelled_or_left_alone() -> Result<()> {
+        let elsewhere = request("elsewhere", Status::CancelRequested);
+        let mut running = create_job_from_request(&elsewhere, JobType::SimulatedJob, "metadata");
+        running.transition(Status::Queued, None)?;
+        running.transition(Status::Processing, None)?;
+        let finished = request("finished", Status::CancelRequested);
+        let mut completed = create_job_from_request(&finished, JobType::SimulatedJob, "metadata");
+        completed.transition(Status::Queued, None)?;
+        completed.transition(Status::Processing, None)?;
+        completed.transition(Status::Completed, None)?;
+
+        let requests = MemoryRequests::new(vec![
+            request("never-queued", Status::CancelRequested),
+            elsewhere,
+            finished,
+        ]);
+        let jobs = MemoryJobs::new(vec![running, completed]);
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let topics = publisher.topics().await?;
+        let job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()));
+
+        cancel_requested(&requests, &jobs, &publisher, &topics, &job_queue).await?;
+
+        let status = |request_id| requests.get(request_id).unwrap().status;
+        assert_eq!(status("never-queued"), Status::Cancelled);
+        assert_eq!(status("elsewhere"), Status::CancelRequested);
+        assert_eq!(status("finished"), Status::Completed);
+        let mut updates = published(&publisher);
+        updates.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            updates,
+            vec![
+                ("finished".to_string(), Status::Completed),
+                ("never-queued".to_string(), Status::Cancelled),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    config::Config,
+    models::{job_queue::JobQueue, job_request::JobRequest, status::Status},
+    store::{EventPublisher, JobStore, Message, MessageQueue, RequestStore},
+    tasks::{
+        cancel::{cancel_requested, watch_cancellations},
+        queue::{publish_complete_requests, queue_request},
+        recovery::recover_jobs,
+        serve::shutdown_signal,
+    },
+};
+use eyre::Result;
+use log::{debug, error, info, warn};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Keeps the `leased` messages hidden by extending their lease every half `lease`,
+/// until the future is dropped.
+async fn heartbeat(messages: &dyn MessageQueue, leased: &[Message], lease: Duration) {
+    loop {
+        sleep(lease / 2).await;
+        for message in leased {
+            if let Err(err) = messages.extend_lease(message, lease).await {
+                warn!(
+                    "Extending the lease of message {} failed: {err:#}",
+                    message.id
+                );
+            }
+        }
+    }
+}
+
+/// Claims and queues the request of `message`, returning whether its jobs were queued. The message
+/// is deleted right away if its request was already claimed.
+async fn queue_message(
+    requests: &dyn RequestStore,
+    publisher: &dyn EventPublisher,
+    messages: &dyn MessageQueue,
+    config: &Config,
+    topics: &[String],
+    job_queue: &mut JobQueue,
+    message: &Message,
+) -> Result<bool> {
+    let job_request: JobRequest = match serde_json::from_str(&message.body) {
+        Ok(job_request) => job_request,
+        Err(err) => {
+            error!("Message {} is not a request: {err}", message.id);
+            return Ok(false);
+        }
+    };
+    let request_id = job_request.request_id;
+    let Some(stored) = requests
+        .requests_with_id(&request_id)
+        .await?
+        .into_iter()
+        .next()
+    else {
+        warn!(
+            "Request {} of message {} is not in the requests table",
+            request_id, message.id
+        );
+        return Ok(false);
+    };
+
+    let queued = stored.status == Status::Pending
+        && queue_request(requests, publisher, config, topics, job_queue, stored).await?;
+    if !queued {
+        debug!(
+            "Request {} was already claimed, deleting message {}",
+            request_id, message.id
+        );
+        messages.delete(message).await?;
+    }
+    Ok(queued)
+}
+
+/// Queues the request of every message in `batch` and runs the jobs, extending the leases of
+/// their messages meanwhile. A message is deleted once its request's jobs have been stored, run
+/// and published, or right away if its request was already claimed. Messages that are not
+/// requests, whose request is not in the requests table, or whose request failed to queue, are
+/// left to come back once their visibility timeout lapses, or to be dead-lettered.
+pub async fn process_messages(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    messages: &dyn MessageQueue,
+    config: &Config,
+    job_queue: &mut JobQueue,
+    batch: Vec<Message>,
+) -> Result<()> {
+    let topics = config.topics.select(publisher.topics().await?);
+    if let Err(err) = cancel_requested(requests, jobs, publisher, &topics, job_queue).await {
+        error!("Checking for cancelled requests failed: {err:#}");
+    }
+
+    let mut leased = Vec::new();
+    for message in batch {
+        let queued = queue_message(
+            requests, publisher, messages, config, &topics, job_queue, &message,
+        )
+        .await;
+        match queued {
+            Ok(true) => leased.push(message),
+            Ok(false) => {}
+            Err(err) => error!(
+                "Queueing the request of message {} failed: {err:#}",
+                message.id
+            ),
+        }
+    }
+    debug!("{:#}", job_queue);
+
+    let interval = config.queue.interval();
+    let lease = config.intake.visibility_timeout();
+    tokio::select! {
+        result = job_queue.run() => result?,
+        () = watch_cancellations(requests, jobs, publisher, &topics, job_queue, interval) => {}
+        () = heartbeat(messages, &leased, lease) => {}
+    }
+    publish_complete_requests(requests, jobs, publisher, &topics, job_queue).await?;
+    debug!("{:#}", job_queue);
+
+    for message in leased.iter() {
+        if let Err(err) = messages.delete(message).await {
+            error!("Deleting message {} failed: {err:#}", message.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Receives requests from `messages` until a shutdown signal arrives, processing each batch on a
+/// fresh queue built by `new_queue`. The first one also picks up the jobs this worker left
+/// unfinished in the job store. As in `serve`, a signal never interrupts a running job, and the
+/// jobs not started yet are left QUEUED.
+pub async fn intake(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    messages: &dyn MessageQueue,
+    config: &Config,
+    new_queue: impl Fn() -> JobQueue,
+) -> Result<()> {
+    let mut shutdown = shutdown_signal()?;
+    info!("Receiving requests from {}", config.intake.queue);
+
+    let mut job_queue = new_queue().with_shutdown(shutdown.clone());
+    if let Err(err) = recover_jobs(requests, jobs, config, &mut job_queue).await {
+        error!("Job recovery failed: {err:#}");
+    }
+
+    loop {
+        let batch = tokio::select! {
+            batch = messages.receive() => batch,
+            _ = shutdown.changed() => break,
+        };
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(err) => {
+                error!("Receiving requests failed: {err:#}");
+                tokio::select! {
+                    _ = sleep(config.queue.interval()) => {}
+                    _ = shutdown.changed() => break,
+                }
+                Vec::new() // still run any recovered jobs
+            }
+        };
+
+        let result = process_messages(
+            requests,
+            jobs,
+            publisher,
+            messages,
+            config,
+            &mut job_queue,
+            batch,
+        )
+        .await;
+        if let Err(err) = result {
+            error!("Processing requests failed: {err:#}");
+        }
+        job_queue = new_queue().with_shutdown(shutdown.clone());
+
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+
+    info!("Shut down cleanly");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{job::Job, job_response::JobResponse},
+        store::{
+            memory::{MemoryBlobs, MemoryJobs, MemoryMessages, MemoryPublisher, MemoryRequests},
+            StoreFuture,
+        },
+    };
+    use std::sync::Arc;
+
+    fn request(request_id: &str, status: Status) -> JobRequest {
+        JobRequest {
+            analysis_types: vec!["Simulated Job".to_string()],
+            id: request_id.to_string(),
+            request_id: request_id.to_string(),
+            author: "test author".to_string(),
+            name: "test job".to_string(),
+            description: "test desc".to_string(),
+            timestamp: 0,
+            status,
+            sources: vec!["simulated".to_string()],
+            range_start: 0,
+            range_end: 1,
+            granularity: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_deleted_once_their_request_is_published() -> Result<()> {
+        let pending = request("pending", Status::Pending);
+        let claimed = request("claimed", Status::Queued);
+        let requests = MemoryRequests::new(vec![pending.clone(), claimed.clone()]);
+        let messages = MemoryMessages::new(&[
+            &serde_json::to_string(&pending)?,
+            &serde_json::to_string(&claimed)?,
+            "not a request",
+            &serde_json::to_string(&request("unknown", Status::Pending))?,
+        ]);
+        let jobs = Arc::new(MemoryJobs::default());
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        let batch = messages.receive().await?;
+        process_messages(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &messages,
+            &Config::default(),
+            &mut job_queue,
+            batch,
+        )
+        .await?;
+
+        assert_eq!(requests.get("pending").unwrap().status, Status::Completed);
+        assert_eq!(
+            jobs.get("pending-simulated").unwrap().status,
+            Status::Completed
+        );
+        assert_eq!(requests.get("claimed").unwrap().status, Status::Queued);
+        // the duplicate goes first, the request's own message once it is published
+        assert_eq!(messages.deleted(), vec!["1", "0"]);
+
+        Ok(())
+    }
+
+    /// Fails to store the jobs of one request.
+    struct FailingJobs {
+        request_id: String,
+        jobs: MemoryJobs,
+    }
+
+    impl JobStore for FailingJobs {
+        fn put_job<'a>(&'a self, job: &'a Job) -> StoreFuture<'a, ()> {
+            if job.request_id == self.request_id {
+                return Box::pin(async { Err(eyre::eyre!("put_job failed")) });
+            }
+            self.jobs.put_job(job)
+        }
+
+        fn unfinished_jobs<'a>(&'a self, worker_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+            self.jobs.unfinished_jobs(worker_id)
+        }
+
+        fn request_jobs<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<Job>> {
+            self.jobs.request_jobs(request_id)
+        }
+
+        fn put_response<'a>(&'a self, response: &'a JobResponse) -> StoreFuture<'a, ()> {
+            self.jobs.put_response(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_kept_when_their_jobs_are_not_stored() -> Result<()> {
+        let failing = request("failing", Status::Pending);
+        let stored = request("stored", Status::Pending);
+        let requests = MemoryRequests::new(vec![failing.clone(), stored.clone()]);
+        let messages = MemoryMessages::new(&[
+            &serde_json::to_string(&failing)?,
+            &serde_json::to_string(&stored)?,
+        ]);
+        let jobs = Arc::new(FailingJobs {
+            request_id: "failing".to_string(),
+            jobs: MemoryJobs::default(),
+        });
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        let batch = messages.receive().await?;
+        process_messages(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &messages,
+            &Config::default(),
+            &mut job_queue,
+            batch,
+        )
+        .await?;
+
+        // the failing request is released and its message left for a later delivery
+        assert_eq!(requests.get("failing").unwrap().status, Status::Pending);
+        assert_eq!(requests.get("stored").unwrap().status, Status::Completed);
+        assert_eq!(messages.deleted(), vec!["1"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leases_are_extended_while_jobs_run() {
+        let messages = MemoryMessages::new(&["{}"]);
+        let leased = messages.receive().await.unwrap();
+
+        let lease = Duration::from_millis(20);
+        let _ = tokio::time::timeout(
+            Duration::from_millis(35),
+            heartbeat(&messages, &leased, lease),
+        )
+        .await;
+
+        assert!(!messages.extended().is_empty());
+        assert!(messages.deleted().is_empty());
+    }
+}
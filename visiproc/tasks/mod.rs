@@ -1 +1,5 @@
+pub mod cancel;
+pub mod intake;
 pub mod queue;
+pub mod recovery;
+pub mod serve;
@@ -1,154 +1,309 @@
 use crate::{
     analysis::analysis_jobs::create_job_instance,
-    aws::sns::publish,
+    config::Config,
     models::{
-        job::create_job_from_request,
+        job::{create_job_from_request, Job},
         job_queue::JobQueue,
-        job_request::{convert_item_to_job_request, JobRequest},
+        job_request::{JobRequest, RequestUpdate},
+        job_response::create_job_response,
         job_type::JobType,
         status::Status,
     },
+    store::{EventPublisher, JobStore, RequestStore},
 };
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
-use aws_sdk_sns::Client as SnsClient;
-use eyre::{Report, Result};
-use log::debug;
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use eyre::Result;
+use log::{debug, error, info, warn};
+use std::sync::{Arc, Mutex};
 
-pub async fn scan_for(
-    dynamodb_client: &DynamoDbClient,
-    table: &str,
-    attr_name: &str,
-    attr_val: &str,
-) -> Result<Vec<HashMap<String, AttributeValue>>> {
-    let result = dynamodb_client
-        .scan()
-        .table_name(table)
-        .filter_expression("#st = :val")
-        .expression_attribute_names("#st", attr_name)
-        .expression_attribute_values(":val", AttributeValue::S(attr_val.to_string()))
-        .send()
-        .await?;
+/// Moves the request to `status` if `Status::transition` allows it, returning `None` when
+/// another worker moved it first.
+pub async fn update_request_status(
+    requests: &dyn RequestStore,
+    job_request: &JobRequest,
+    status: Status,
+) -> Result<Option<JobRequest>> {
+    let status = job_request.status.transition(status)?;
+    requests.set_status(job_request, status).await
+}
 
-    Ok(result.items().to_vec())
+/// Publishes the request's current state to every topic.
+pub async fn publish_request(
+    publisher: &dyn EventPublisher,
+    topics: &[String],
+    job_request: &JobRequest,
+) -> Result<()> {
+    let json_string = serde_json::to_string(&RequestUpdate::from(job_request))?;
+    for topic in topics.iter() {
+        publisher.publish(topic, &json_string).await?;
+    }
+    Ok(())
 }
 
-pub async fn update_request_status(
-    dynamodb_client: &DynamoDbClient,
-    item: &HashMap<String, AttributeValue>,
-    current_status: &Status,
-    table: &str,
-) -> Result<HashMap<String, AttributeValue>> {
-    if let Some(new_status) = current_status.next() {
-        let new_status_str = new_status.to_string();
-        let mut updated_item = item.clone();
-        updated_item.insert(
-            "jobStatus".to_string(),
-            AttributeValue::S(new_status_str.clone()),
-        );
+/// Adds the analysis that `job` runs to `job_queue`.
+pub fn queue_job(job: Job, config: &Config, job_queue: &mut JobQueue) -> Arc<Mutex<Job>> {
+    let job_impl = create_job_instance(job.job_type, config);
+    let job_metadata = Arc::new(Mutex::new(job));
+    job_queue.add_job(job_impl, job_metadata.clone());
+    job_metadata
+}
 
-        let update_request_builder = dynamodb_client
-            .update_item()
-            .table_name(table)
-            .key("requestID".to_string(), item["requestID"].clone())
-            .key("creationDate", item["creationDate"].clone())
-            .update_expression("SET #st = :status_val")
-            .expression_attribute_names("#st", "jobStatus")
-            .expression_attribute_values(":status_val", AttributeValue::S(new_status_str));
+/// Queues a job for each analysis type of `job_request`.
+pub fn queue_jobs_from_request(
+    job_request: &JobRequest,
+    config: &Config,
+    job_queue: &mut JobQueue,
+) -> Result<Vec<Arc<Mutex<Job>>>> {
+    JobType::from_request(job_request)
+        .into_iter()
+        .map(|job_type| {
+            let mut job = create_job_from_request(job_request, job_type, &config.buckets.results);
+            job.transition(Status::Queued, None)?;
+            Ok(queue_job(job, config, job_queue))
+        })
+        .collect()
+}
 
-        update_request_builder.send().await?;
-        Ok(updated_item)
-    } else {
-        Err(Report::msg("No next status available"))
+/// Claims and queues every PENDING request. A request that fails to queue is logged and released,
+/// and the rest are still queued.
+pub async fn queue_new_requests(
+    requests: &dyn RequestStore,
+    publisher: &dyn EventPublisher,
+    config: &Config,
+    topics: &[String],
+    job_queue: &mut JobQueue,
+) -> Result<()> {
+    for job_request in requests.requests_with_status(Status::Pending).await? {
+        let request_id = job_request.request_id.clone();
+        if let Err(err) =
+            queue_request(requests, publisher, config, topics, job_queue, job_request).await
+        {
+            error!("Queueing request {} failed: {err:#}", request_id);
+        }
     }
+
+    Ok(())
 }
 
-pub fn queue_jobs_from_request(job_request: &JobRequest, job_queue: &mut JobQueue) -> Result<()> {
-    let job_metadata = Arc::new(Mutex::new(create_job_from_request(job_request)));
-    let analysis_types = JobType::from_request(job_request);
+/// Claims the PENDING `job_request` and queues its jobs, returning false when another worker
+/// claimed it first. If queueing fails once the request is claimed, the claim is released before
+/// the error is returned.
+pub async fn queue_request(
+    requests: &dyn RequestStore,
+    publisher: &dyn EventPublisher,
+    config: &Config,
+    topics: &[String],
+    job_queue: &mut JobQueue,
+    job_request: JobRequest,
+) -> Result<bool> {
+    // claim the request before queueing it, so competing workers never both run it
+    let Some(job_request) = update_request_status(requests, &job_request, Status::Queued).await?
+    else {
+        debug!("Request {} was claimed elsewhere", job_request.request_id);
+        return Ok(false);
+    };
 
-    for job_type in analysis_types {
-        let job_clone = job_metadata.clone();
-        {
-            let mut job = job_clone.lock().unwrap();
-            if let Some(new_status) = job.status.next() {
-                job.status = new_status;
-            }
-        } // Lock is dropped here
+    match queue_claimed(requests, publisher, config, topics, job_queue, &job_request).await {
+        Ok(()) => Ok(true),
+        Err(err) => {
+            release_claim(requests, job_queue, &job_request).await;
+            Err(err)
+        }
+    }
+}
+
+/// Queues the jobs of the claimed `job_request` and publishes it.
+async fn queue_claimed(
+    requests: &dyn RequestStore,
+    publisher: &dyn EventPublisher,
+    config: &Config,
+    topics: &[String],
+    job_queue: &mut JobQueue,
+    job_request: &JobRequest,
+) -> Result<()> {
+    let queued = queue_jobs_from_request(job_request, config, job_queue)?;
+    for job_metadata in queued.iter() {
+        job_queue.try_persist(job_metadata).await?;
+    }
+
+    // publish message about the queued job
+    publish_request(publisher, topics, job_request).await?;
+    debug!("Published queued job {:#?}", job_request);
 
-        let job_impl = create_job_instance(job_type);
-        job_queue.add_job(job_impl, job_clone);
+    if queued.is_empty() {
+        warn!(
+            "Request {} has no known analysis types",
+            job_request.request_id
+        );
+        follow_jobs(requests, publisher, topics, job_request.clone(), &[]).await?;
     }
 
     Ok(())
 }
 
-pub async fn queue_new_requests(
-    dynamodb_client: &DynamoDbClient,
-    sns_client: &SnsClient,
-    topics: &Vec<String>,
+/// Undoes the claim of a request whose jobs failed to queue. Its jobs leave the queue and their
+/// stored records are cancelled, so that no restart recovers them, and the request goes back to
+/// PENDING for a later cycle to retry.
+async fn release_claim(
+    requests: &dyn RequestStore,
     job_queue: &mut JobQueue,
+    job_request: &JobRequest,
+) {
+    for job_metadata in job_queue.remove_request(&job_request.request_id) {
+        let cancelled = job_metadata
+            .lock()
+            .unwrap()
+            .transition(Status::Cancelled, Some("Request released"))
+            .is_ok();
+        if cancelled {
+            job_queue.persist(&job_metadata).await;
+        }
+    }
+
+    match update_request_status(requests, job_request, Status::Pending).await {
+        Ok(Some(_)) => info!("Released request {}", job_request.request_id),
+        Ok(None) => warn!(
+            "Request {} changed before it could be released",
+            job_request.request_id
+        ),
+        Err(err) => error!(
+            "Releasing request {} failed: {err:#}",
+            job_request.request_id
+        ),
+    }
+}
+
+/// The queue's jobs grouped by the request they belong to, keeping queue order.
+fn jobs_by_request(job_queue: &JobQueue) -> Vec<(String, Vec<Arc<Mutex<Job>>>)> {
+    let mut groups: Vec<(String, Vec<Arc<Mutex<Job>>>)> = Vec::new();
+    for job_metadata in job_queue.jobs() {
+        let request_id = job_metadata.lock().unwrap().request_id.clone();
+        match groups.iter_mut().find(|(id, _)| *id == request_id) {
+            Some((_, queued)) => queued.push(job_metadata),
+            None => groups.push((request_id, vec![job_metadata])),
+        }
+    }
+    groups
+}
+
+/// Every job of the request: the queued ones as they are now, and the stored ones that are not
+/// in this queue, e.g. because they finished before a restart.
+async fn request_jobs(
+    jobs: &dyn JobStore,
+    request_id: &str,
+    queued: &[Arc<Mutex<Job>>],
+) -> Result<Vec<Job>> {
+    let mut request_jobs: Vec<Job> = queued
+        .iter()
+        .map(|job_metadata| job_metadata.lock().unwrap().clone())
+        .collect();
+    for job in jobs.request_jobs(request_id).await? {
+        if !request_jobs
+            .iter()
+            .any(|queued| queued.job_id == job.job_id)
+        {
+            request_jobs.push(job);
+        }
+    }
+    Ok(request_jobs)
+}
+
+/// Records a `JobResponse` for the finished request and points each of its jobs at it.
+async fn record_job_response(
+    jobs: &dyn JobStore,
+    job_queue: &JobQueue,
+    request_id: &str,
+    queued: &[Arc<Mutex<Job>>],
+    request_jobs: &[Job],
 ) -> Result<()> {
-    for item in scan_for(dynamodb_client, "mockRequests", "jobStatus", "PENDING").await? {
-        let job_request = convert_item_to_job_request(&item)?;
+    if request_jobs
+        .iter()
+        .all(|job| !job.current_response_id.is_empty())
+    {
+        return Ok(()); // recorded by an earlier call
+    }
+
+    let response = create_job_response(request_id, request_jobs);
+    jobs.put_response(&response).await?;
+    debug!(
+        "Recorded response {} for request {}",
+        response.response_id, request_id
+    );
 
-        // queue the job
-        queue_jobs_from_request(&job_request, job_queue)?;
+    for job_metadata in queued.iter() {
+        job_metadata.lock().unwrap().current_response_id = response.response_id.clone();
+        job_queue.try_persist(job_metadata).await?;
+    }
+    for job in request_jobs.iter().skip(queued.len()) {
+        let job = Job {
+            current_response_id: response.response_id.clone(),
+            ..job.clone()
+        };
+        jobs.put_job(&job).await?;
+    }
+
+    Ok(())
+}
 
-        let updated_item =
-            update_request_status(dynamodb_client, &item, &job_request.status, "mockRequests")
-                .await?;
-        let job_request = convert_item_to_job_request(&updated_item)?;
+/// Moves the request to the rollup of its jobs' statuses, through PROCESSING if any of them
+/// started, publishing each step.
+pub async fn follow_jobs(
+    requests: &dyn RequestStore,
+    publisher: &dyn EventPublisher,
+    topics: &[String],
+    mut job_request: JobRequest,
+    request_jobs: &[Job],
+) -> Result<()> {
+    let statuses: Vec<Status> = request_jobs.iter().map(|job| job.status.clone()).collect();
+    let rollup = Status::rollup(&statuses);
+    let started = request_jobs.iter().any(|job| {
+        job.history
+            .iter()
+            .any(|change| change.to == Status::Processing)
+    });
 
-        // publish message about the queued job
-        let json_string = serde_json::to_string(&job_request)?;
-        for topic in topics.iter() {
-            publish(sns_client, topic, &json_string).await?;
+    for status in [started.then_some(Status::Processing), Some(rollup.clone())]
+        .into_iter()
+        .flatten()
+    {
+        if !job_request.status.can_transition(&status) {
+            continue;
         }
-        debug!("Published queued job {:#?}", updated_item);
+        let Some(updated) = update_request_status(requests, &job_request, status).await? else {
+            debug!("Request {} changed concurrently", job_request.request_id);
+            return Ok(());
+        };
+        job_request = updated;
+        publish_request(publisher, topics, &job_request).await?;
     }
 
+    if job_request.status != rollup {
+        warn!(
+            "Request {} is {} but its jobs roll up to {}",
+            job_request.request_id, job_request.status, rollup
+        );
+    }
     Ok(())
 }
 
+/// Publishes the rolled up status of every request whose jobs in the queue have all finished.
 pub async fn publish_complete_requests(
-    dynamodb_client: &DynamoDbClient,
-    sns_client: &SnsClient,
-    topics: &Vec<String>,
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    topics: &[String],
     job_queue: &mut JobQueue,
 ) -> Result<()> {
-    for job_arc in job_queue.iter() {
-        let job = job_arc.lock().unwrap();
-
-        if job.status == Status::Completed {
-            debug!("Job Info: {:?}", *job_arc);
-            for item in scan_for(
-                dynamodb_client,
-                "mockRequests",
-                "requestID",
-                &job.request_id,
-            )
-            .await?
-            {
-                let updated_item = update_request_status(
-                    dynamodb_client,
-                    &item,
-                    &Status::Processing,
-                    "mockRequests",
-                )
-                .await?;
-                let job_request = convert_item_to_job_request(&updated_item)?;
-
-                let json_string = serde_json::to_string(&job_request)?;
-                for topic in topics.iter() {
-                    publish(sns_client, topic, &json_string).await?;
-                }
-            }
-        } else {
-            debug!("Job Info (Not Complete): {:?}", *job);
+    for (request_id, queued) in jobs_by_request(job_queue) {
+        let request_jobs = request_jobs(jobs, &request_id, &queued).await?;
+        if !request_jobs.iter().all(|job| job.status.is_final()) {
+            debug!("Request {} (Not Complete): {:?}", request_id, request_jobs);
+            continue;
+        }
+
+        debug!("Request {}: {:?}", request_id, request_jobs);
+        record_job_response(jobs, job_queue, &request_id, &queued, &request_jobs).await?;
+        for job_request in requests.requests_with_id(&request_id).await? {
+            follow_jobs(requests, publisher, topics, job_request, &request_jobs).await?;
         }
     }
 
@@ -158,6 +313,10 @@ pub async fn publish_complete_requests(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{
+        memory::{MemoryBlobs, MemoryJobs, MemoryPublisher, MemoryRequests},
+        StoreFuture,
+    };
     use uuid::Uuid;
 
     pub fn generate_request_id() -> String {
@@ -169,14 +328,8 @@ mod tests {
             .collect::<String>()
     }
 
-    #[tokio::test]
-    async fn test_simulated_job_run() -> Result<()> {
-        let mut job_queue = JobQueue::new();
-
-        // empty queue
-        println!("{:#}", job_queue);
-
-        let job_request = JobRequest {
+    fn simulated_request() -> JobRequest {
+        JobRequest {
             analysis_types: vec![
                 "Simulated Job".to_string(),
                 // "Exploratory Data Analysis".to_string(),
@@ -193,9 +346,17 @@ mod tests {
             range_start: 0,
             range_end: 1,
             granularity: 0,
-        };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulated_job_run() -> Result<()> {
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()));
+
+        // empty queue
+        println!("{:#}", job_queue);
 
-        queue_jobs_from_request(&job_request, &mut job_queue)?;
+        queue_jobs_from_request(&simulated_request(), &Config::default(), &mut job_queue)?;
         println!("{:#}", job_queue);
 
         // run jobs
@@ -204,4 +365,244 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn pending_requests_are_run_and_published() -> Result<()> {
+        let job_request = simulated_request();
+        let request_id = job_request.request_id.clone();
+        let requests = MemoryRequests::new(vec![job_request]);
+        let jobs = Arc::new(MemoryJobs::default());
+        let blobs = Arc::new(MemoryBlobs::default());
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let topics = publisher.topics().await?;
+        let mut job_queue = JobQueue::new(blobs.clone()).with_job_store(jobs.clone());
+
+        queue_new_requests(
+            &requests,
+            &publisher,
+            &Config::default(),
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+        assert_eq!(requests.get(&request_id).unwrap().status, Status::Queued);
+
+        job_queue.run().await?;
+        publish_complete_requests(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+
+        assert_eq!(requests.get(&request_id).unwrap().status, Status::Completed);
+        let published: Vec<Status> = publisher
+            .messages()
+            .iter()
+            .map(|(_, message)| {
+                serde_json::from_str::<RequestUpdate>(message)
+                    .unwrap()
+                    .status
+            })
+            .collect();
+        assert_eq!(
+            published,
+            vec![Status::Queued, Status::Processing, Status::Completed]
+        );
+
+        let job_id = format!("{request_id}-simulated");
+        let job = jobs.get(&job_id).unwrap();
+        let responses = jobs.responses();
+        assert_eq!(job.status, Status::Completed);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].job_status, vec![Status::Completed]);
+        assert_eq!(job.current_response_id, responses[0].response_id);
+
+        let log = format!("s3://metadata/{request_id}/{job_id}.log");
+        assert!(blobs.get(&log).is_some());
+        assert!(job.artifacts.iter().any(|artifact| artifact.uri == log));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_publish_the_rollup_of_their_jobs() -> Result<()> {
+        let job_request = JobRequest {
+            analysis_types: vec!["Simulated Job".to_string(), "Simulated Error".to_string()],
+            ..simulated_request()
+        };
+        let request_id = job_request.request_id.clone();
+        let requests = MemoryRequests::new(vec![job_request]);
+        let jobs = Arc::new(MemoryJobs::default());
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let topics = publisher.topics().await?;
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        let config = Config::default();
+        queue_new_requests(&requests, &publisher, &config, &topics, &mut job_queue).await?;
+        job_queue.run().await?;
+        publish_complete_requests(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+
+        let simulated = jobs.get(&format!("{request_id}-simulated")).unwrap();
+        let error = jobs.get(&format!("{request_id}-simulated-error")).unwrap();
+        assert_eq!(simulated.status, Status::Completed);
+        assert_eq!(error.status, Status::Failed);
+        assert_eq!(requests.get(&request_id).unwrap().status, Status::Failed);
+
+        let responses = jobs.responses();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].job_type,
+            vec![JobType::SimulatedJob, JobType::SimulatedError]
+        );
+        assert_eq!(
+            responses[0].job_status,
+            vec![Status::Completed, Status::Failed]
+        );
+        assert_eq!(simulated.current_response_id, responses[0].response_id);
+        assert_eq!(error.current_response_id, responses[0].response_id);
+
+        // publishing again neither records another response nor republishes
+        let published = publisher.messages().len();
+        publish_complete_requests(
+            &requests,
+            jobs.as_ref(),
+            &publisher,
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+        assert_eq!(jobs.responses().len(), 1);
+        assert_eq!(publisher.messages().len(), published);
+
+        Ok(())
+    }
+
+    /// Fails to publish anything about one request.
+    struct FailingPublisher {
+        request_id: String,
+        publisher: MemoryPublisher,
+    }
+
+    impl EventPublisher for FailingPublisher {
+        fn topics(&self) -> StoreFuture<'_, Vec<String>> {
+            self.publisher.topics()
+        }
+
+        fn publish<'a>(&'a self, topic: &'a str, message: &'a str) -> StoreFuture<'a, ()> {
+            if message.contains(&self.request_id) {
+                return Box::pin(async { Err(eyre::eyre!("publish failed")) });
+            }
+            self.publisher.publish(topic, message)
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_that_fail_to_queue_are_released() -> Result<()> {
+        let failing = simulated_request();
+        let queued = simulated_request();
+        let requests = MemoryRequests::new(vec![failing.clone(), queued.clone()]);
+        let jobs = Arc::new(MemoryJobs::default());
+        let publisher = FailingPublisher {
+            request_id: failing.request_id.clone(),
+            publisher: MemoryPublisher::new(&["updates"]),
+        };
+        let topics = publisher.topics().await?;
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        queue_new_requests(
+            &requests,
+            &publisher,
+            &Config::default(),
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+
+        let failing_id = &failing.request_id;
+        assert_eq!(requests.get(failing_id).unwrap().status, Status::Pending);
+        let released = jobs.get(&format!("{failing_id}-simulated")).unwrap();
+        assert_eq!(released.status, Status::Cancelled);
+
+        let queued_id = &queued.request_id;
+        assert_eq!(requests.get(queued_id).unwrap().status, Status::Queued);
+        let jobs: Vec<String> = job_queue
+            .jobs()
+            .iter()
+            .map(|job| job.lock().unwrap().request_id.clone())
+            .collect();
+        assert_eq!(jobs, vec![queued_id.clone()]);
+
+        Ok(())
+    }
+
+    /// Lists requests as they were when another worker read them, before it claimed them.
+    struct StaleListing {
+        listed: Vec<JobRequest>,
+        store: MemoryRequests,
+    }
+
+    impl RequestStore for StaleListing {
+        fn requests_with_status(&self, _status: Status) -> StoreFuture<'_, Vec<JobRequest>> {
+            Box::pin(async { Ok(self.listed.clone()) })
+        }
+
+        fn requests_with_id<'a>(&'a self, request_id: &'a str) -> StoreFuture<'a, Vec<JobRequest>> {
+            self.store.requests_with_id(request_id)
+        }
+
+        fn set_status<'a>(
+            &'a self,
+            request: &'a JobRequest,
+            status: Status,
+        ) -> StoreFuture<'a, Option<JobRequest>> {
+            self.store.set_status(request, status)
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_claimed_elsewhere_are_skipped() -> Result<()> {
+        let job_request = simulated_request();
+        let requests = StaleListing {
+            listed: vec![job_request.clone()],
+            store: MemoryRequests::new(vec![job_request.clone()]),
+        };
+
+        // another worker claims the request after both listed it as PENDING
+        let claimed = update_request_status(&requests, &job_request, Status::Queued).await?;
+        assert_eq!(claimed.unwrap().status, Status::Queued);
+        assert!(
+            update_request_status(&requests, &job_request, Status::Queued)
+                .await?
+                .is_none()
+        );
+
+        let publisher = MemoryPublisher::new(&["updates"]);
+        let topics = publisher.topics().await?;
+        let mut job_queue = JobQueue::new(Arc::new(MemoryBlobs::default()));
+        queue_new_requests(
+            &requests,
+            &publisher,
+            &Config::default(),
+            &topics,
+            &mut job_queue,
+        )
+        .await?;
+
+        assert!(job_queue.jobs().is_empty());
+        assert!(publisher.messages().is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,125 @@
+use crate::{
+    config::Config,
+    models::{job::Job, job_queue::JobQueue, status::Status},
+    store::{JobStore, RequestStore},
+    tasks::queue::queue_job,
+    utils::worker_id,
+};
+use eyre::Result;
+use log::{info, warn};
+use std::sync::{Arc, Mutex};
+
+/// Reloads the jobs this worker left QUEUED, PROCESSING or RETRYING in the job store and puts them back on `job_queue`.
+///
+/// QUEUED jobs never started and are resumed as they are. PROCESSING and RETRYING jobs were interrupted
+/// part way through their runs: they are re-queued if their analysis type's retry policy allows another attempt,
+/// and marked FAILED otherwise so that `publish_complete_requests` reports them.
+pub async fn recover_jobs(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    config: &Config,
+    job_queue: &mut JobQueue,
+) -> Result<()> {
+    for mut job in jobs.unfinished_jobs(worker_id()).await? {
+        if requests.requests_with_id(&job.request_id).await?.is_empty() {
+            warn!(
+                "Dropping job {}, request {} not found",
+                job.job_id, job.request_id
+            );
+            fail(
+                &mut job,
+                "Originating request not found after a visiproc restart",
+            )?;
+            job_queue.persist(&Arc::new(Mutex::new(job))).await;
+            continue;
+        }
+
+        if matches!(job.status, Status::Processing | Status::Retrying) {
+            let resumable = job.attempts < job_queue.retry_policy(job.job_type).max_attempts;
+
+            if resumable {
+                job.transition(Status::Queued, Some("Re-queued after a visiproc restart"))?;
+            } else {
+                fail(&mut job, "Interrupted by a visiproc restart")?;
+            }
+        }
+        info!("Recovered job {} as {}", job.job_id, job.status);
+
+        let job_metadata = queue_job(job, config, job_queue);
+        job_queue.persist(&job_metadata).await;
+    }
+
+    Ok(())
+}
+
+fn fail(job: &mut Job, reason: &str) -> Result<()> {
+    job.transition(Status::Failed, Some(reason))?;
+    job.error = Some(reason.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{job::create_job_from_request, job_request::JobRequest, job_type::JobType},
+        store::memory::{MemoryBlobs, MemoryJobs, MemoryRequests},
+    };
+
+    fn job_request(request_id: &str) -> JobRequest {
+        JobRequest {
+            id: request_id.to_string(),
+            request_id: request_id.to_string(),
+            author: "test author".to_string(),
+            name: "test job".to_string(),
+            description: "test desc".to_string(),
+            analysis_types: vec!["Exploratory Data Analysis".to_string()],
+            timestamp: 0,
+            status: Status::Queued,
+            sources: vec!["simulated".to_string()],
+            range_start: 0,
+            range_end: 1,
+            granularity: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_jobs_are_requeued_and_orphans_failed() -> Result<()> {
+        let mut interrupted =
+            create_job_from_request(&job_request("interrupted"), JobType::Eda, "metadata");
+        interrupted.transition(Status::Queued, None)?;
+        interrupted.transition(Status::Processing, None)?;
+        interrupted.attempts = 1;
+        let mut orphan = create_job_from_request(&job_request("orphan"), JobType::Eda, "metadata");
+        orphan.transition(Status::Queued, None)?;
+
+        let requests = MemoryRequests::new(vec![job_request("interrupted")]);
+        let jobs = Arc::new(MemoryJobs::new(vec![interrupted, orphan]));
+        let mut job_queue =
+            JobQueue::new(Arc::new(MemoryBlobs::default())).with_job_store(jobs.clone());
+
+        recover_jobs(&requests, jobs.as_ref(), &Config::default(), &mut job_queue).await?;
+
+        let queued: Vec<(String, Status)> = job_queue
+            .jobs()
+            .into_iter()
+            .map(|job| {
+                let job = job.lock().unwrap();
+                (job.job_id.clone(), job.status.clone())
+            })
+            .collect();
+        assert_eq!(
+            queued,
+            vec![("interrupted-eda".to_string(), Status::Queued)]
+        );
+        let interrupted = jobs.get("interrupted-eda").unwrap();
+        assert_eq!(interrupted.status, Status::Queued);
+        assert_eq!(
+            interrupted.history.last().unwrap().reason.as_deref(),
+            Some("Re-queued after a visiproc restart")
+        );
+        assert_eq!(jobs.get("orphan-eda").unwrap().status, Status::Failed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    config::Config,
+    models::job_queue::JobQueue,
+    store::{EventPublisher, JobStore, RequestStore},
+    tasks::{
+        cancel::{cancel_requested, watch_cancellations},
+        queue::{publish_complete_requests, queue_new_requests},
+        recovery::recover_jobs,
+    },
+};
+use eyre::Result;
+use log::{debug, error, info};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    time::sleep,
+};
+
+/// Runs a single scan/queue/run/publish cycle against the requests table.
+/// Requests moved to CANCEL_REQUESTED are checked before queueing and every
+/// `config.queue.interval` seconds while the jobs run. Failing to check for cancellations or to
+/// queue new requests is logged, and the jobs already queued are still run and published.
+pub async fn process_requests(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    config: &Config,
+    job_queue: &mut JobQueue,
+) -> Result<()> {
+    let topics = config.topics.select(publisher.topics().await?);
+
+    if let Err(err) = cancel_requested(requests, jobs, publisher, &topics, job_queue).await {
+        error!("Checking for cancelled requests failed: {err:#}");
+    }
+    if let Err(err) = queue_new_requests(requests, publisher, config, &topics, job_queue).await {
+        error!("Queueing new requests failed: {err:#}");
+    }
+    debug!("{:#}", job_queue);
+
+    let interval = config.queue.interval();
+    tokio::select! {
+        result = job_queue.run() => result?,
+        () = watch_cancellations(requests, jobs, publisher, &topics, job_queue, interval) => {}
+    }
+    publish_complete_requests(requests, jobs, publisher, &topics, job_queue).await?;
+    debug!("{:#}", job_queue);
+
+    Ok(())
+}
+
+/// Spawns a task that flips the returned receiver to `true` on SIGINT or SIGTERM.
+pub fn shutdown_signal() -> Result<watch::Receiver<bool>> {
+    let (tx, rx) = watch::channel(false);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM"),
+            _ = sigint.recv() => info!("Received SIGINT"),
+        }
+        let _ = tx.send(true);
+    });
+
+    Ok(rx)
+}
+
+/// Polls for PENDING requests every `config.queue.interval` seconds until a shutdown signal arrives.
+/// Each cycle runs on a fresh queue built by `new_queue`; the first one also picks up
+/// the jobs this worker left unfinished in the job store. A cycle that fails keeps its queue for
+/// the next one, so that its jobs are still run and published.
+///
+/// A signal never interrupts a running job. Once it arrives no more jobs are started: the running
+/// ones are finished and published before the loop exits, and the rest are left QUEUED for the
+/// next start to recover.
+pub async fn serve(
+    requests: &dyn RequestStore,
+    jobs: &dyn JobStore,
+    publisher: &dyn EventPublisher,
+    config: &Config,
+    new_queue: impl Fn() -> JobQueue,
+) -> Result<()> {
+    let mut shutdown = shutdown_signal()?;
+    let interval = config.queue.interval();
+    info!("Serving, polling for requests every {:?}", interval);
+
+    let mut job_queue = new_queue().with_shutdown(shutdown.clone());
+    if let Err(err) = recover_jobs(requests, jobs, config, &mut job_queue).await {
+        error!("Job recovery failed: {err:#}");
+    }
+
+    loop {
+        match process_requests(requests, jobs, publisher, config, &mut job_queue).await {
+            Ok(()) => job_queue = new_queue().with_shutdown(shutdown.clone()),
+            Err(err) => error!("Processing cycle failed: {err:#}"),
+        }
+
+        if *shutdown.borrow() {
+            break;
+        }
+
+        tokio::select! {
+            _ = sleep(interval) => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    info!("Shut down cleanly");
+    Ok(())
+}
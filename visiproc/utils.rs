@@ -1,9 +1,20 @@
 use chrono::Local;
 use eyre::Result;
 use fern::InitError;
+use std::sync::OnceLock;
 
-pub fn use_localstack() -> bool {
-    std::env::var("LOCALSTACK").unwrap_or_default() == "true"
+/// Identifies this visiproc instance on the jobs it owns, from `VISIPROC_WORKER_ID` or the hostname.
+/// Looked up once, as it is read while job locks are held.
+pub fn worker_id() -> &'static str {
+    static WORKER_ID: OnceLock<String> = OnceLock::new();
+    WORKER_ID.get_or_init(|| {
+        std::env::var("VISIPROC_WORKER_ID")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .or_else(|_| {
+                std::fs::read_to_string("/etc/hostname").map(|name| name.trim().to_string())
+            })
+            .unwrap_or_else(|_| "visiproc".to_string())
+    })
 }
 
 pub fn init_logging() -> Result<(), InitError> {